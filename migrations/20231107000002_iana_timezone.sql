@@ -0,0 +1,19 @@
+-- 整数時差をIANAタイムゾーン名に置き換え
+CREATE TABLE setting_new (
+    guild_id BIGINT NOT NULL PRIMARY KEY,
+    timezone TEXT NOT NULL DEFAULT 'UTC'
+);
+
+-- Etc/GMT±N は符号が逆転している (UTC+9 => Etc/GMT-9)
+INSERT INTO setting_new (guild_id, timezone)
+SELECT guild_id,
+       CASE
+           WHEN utc_offset = 0 THEN 'UTC'
+           WHEN utc_offset > 0 THEN 'Etc/GMT-' || utc_offset
+           ELSE 'Etc/GMT+' || (-utc_offset)
+       END
+FROM setting;
+
+DROP TABLE setting;
+
+ALTER TABLE setting_new RENAME TO setting;
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS user_setting (
+    user_id BIGINT NOT NULL PRIMARY KEY,
+    timezone TEXT NOT NULL
+);
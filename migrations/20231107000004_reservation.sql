@@ -0,0 +1,49 @@
+-- 切断予約と切断前通知をまとめる予約
+CREATE TABLE IF NOT EXISTS reservation (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id BIGINT NOT NULL,
+    guild_id BIGINT NOT NULL
+);
+
+CREATE TABLE job_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    reservation_id INTEGER NOT NULL REFERENCES reservation(id) ON DELETE CASCADE,
+    naive_utc DATETIME NOT NULL,
+    user_id BIGINT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    event_type INTEGER NOT NULL,
+    UNIQUE(reservation_id, event_type)
+);
+
+-- 既存の切断予約ごとに予約IDを発行
+INSERT INTO reservation (id, user_id, guild_id)
+SELECT rowid, user_id, guild_id FROM job WHERE event_type = 'Disconnect';
+
+INSERT INTO job_new (reservation_id, naive_utc, user_id, guild_id, event_type)
+SELECT rowid, naive_utc, user_id, guild_id, event_type FROM job WHERE event_type = 'Disconnect';
+
+-- 3分前通知は対応する切断予約に紐付ける
+-- 対応する切断予約が見つからない通知は移行しない（DROP TABLE jobで消える）。
+-- 旧版では通知は切断予約と一緒に登録され、取り消しも両方まとめて消していたので、
+-- 対応のない通知は切断予約の登録に失敗した場合などに残ったものだけ。
+-- 切断されない予約の「3分後に切断」通知は送っても誤報になるので捨てる。
+-- 件数は移行前に次のクエリで確認できる:
+--   SELECT count(*) FROM job n WHERE n.event_type = 'Notification3Min' AND NOT EXISTS (
+--     SELECT 1 FROM job d WHERE d.user_id = n.user_id AND d.guild_id = n.guild_id
+--       AND d.event_type = 'Disconnect'
+--       AND abs((julianday(d.naive_utc) - julianday(n.naive_utc)) * 86400 - 180) < 1);
+INSERT INTO job_new (reservation_id, naive_utc, user_id, guild_id, event_type)
+SELECT d.rowid, n.naive_utc, n.user_id, n.guild_id, n.event_type
+FROM job n
+JOIN job d
+  ON d.user_id = n.user_id
+ AND d.guild_id = n.guild_id
+ AND d.event_type = 'Disconnect'
+ AND abs((julianday(d.naive_utc) - julianday(n.naive_utc)) * 86400 - 180) < 1
+WHERE n.event_type = 'Notification3Min';
+
+DROP TABLE job;
+
+ALTER TABLE job_new RENAME TO job;
+
+CREATE INDEX IF NOT EXISTS job_naive_utc ON job(naive_utc);
@@ -0,0 +1,4 @@
+-- 繰り返し予約（現地時刻とタイムゾーンで評価する）
+ALTER TABLE reservation ADD COLUMN recurrence TEXT;
+ALTER TABLE reservation ADD COLUMN local_time TEXT;
+ALTER TABLE reservation ADD COLUMN timezone TEXT;
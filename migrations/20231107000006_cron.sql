@@ -0,0 +1,2 @@
+-- cron式による繰り返し予約
+ALTER TABLE reservation ADD COLUMN cron TEXT;
@@ -0,0 +1,25 @@
+-- 切断前通知のタイミング（分、カンマ区切り）
+ALTER TABLE setting ADD COLUMN warnings TEXT NOT NULL DEFAULT '3';
+ALTER TABLE reservation ADD COLUMN warnings TEXT;
+
+-- 1つの予約に複数の切断前通知を登録できるようにする
+CREATE TABLE job_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    reservation_id INTEGER NOT NULL REFERENCES reservation(id) ON DELETE CASCADE,
+    naive_utc DATETIME NOT NULL,
+    user_id BIGINT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    event_type INTEGER NOT NULL,
+    UNIQUE(reservation_id, event_type, naive_utc)
+);
+
+INSERT INTO job_new (id, reservation_id, naive_utc, user_id, guild_id, event_type)
+SELECT id, reservation_id, naive_utc, user_id, guild_id,
+       CASE event_type WHEN 'Notification3Min' THEN 'Notification' ELSE event_type END
+FROM job;
+
+DROP TABLE job;
+
+ALTER TABLE job_new RENAME TO job;
+
+CREATE INDEX IF NOT EXISTS job_naive_utc ON job(naive_utc);
@@ -0,0 +1,7 @@
+-- 切断前通知からの延長の上限（回数、合計分）
+ALTER TABLE setting ADD COLUMN snooze_count INTEGER NOT NULL DEFAULT 3;
+ALTER TABLE setting ADD COLUMN snooze_minutes INTEGER NOT NULL DEFAULT 30;
+
+-- 今回の切断予約で延長した回数、合計分
+ALTER TABLE reservation ADD COLUMN snoozed_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE reservation ADD COLUMN snoozed_minutes INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- 切断対象（User: user_idのユーザー, Channel: target_idのボイスチャンネル, Server: サーバー全体）
+-- Channel/Serverの予約のuser_idは予約したユーザー
+ALTER TABLE reservation ADD COLUMN target_kind TEXT NOT NULL DEFAULT 'User';
+ALTER TABLE reservation ADD COLUMN target_id BIGINT;
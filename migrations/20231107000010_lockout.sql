@@ -0,0 +1,14 @@
+-- 切断後に通話へ戻れない時間（分）
+ALTER TABLE reservation ADD COLUMN lockout_minutes INTEGER;
+
+-- 有効なロックアウトと再参加を試みた回数
+CREATE TABLE IF NOT EXISTS lockout (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    reservation_id INTEGER NOT NULL REFERENCES reservation(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    until DATETIME NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS lockout_user ON lockout(user_id, guild_id);
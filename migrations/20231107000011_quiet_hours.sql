@@ -0,0 +1,7 @@
+-- ギルドの静粛時間（現地時刻 %H:%M、この時間帯は通話に参加できない）
+ALTER TABLE setting ADD COLUMN quiet_start TEXT;
+ALTER TABLE setting ADD COLUMN quiet_end TEXT;
+-- 対象のロール（NULLなら全員）
+ALTER TABLE setting ADD COLUMN quiet_role_id BIGINT;
+-- 開始時刻の切断と切断前通知を行う毎日の予約
+ALTER TABLE setting ADD COLUMN quiet_reservation_id INTEGER REFERENCES reservation(id) ON DELETE SET NULL;
@@ -0,0 +1,15 @@
+-- 連続通話の上限（分、NULLなら無制限）
+ALTER TABLE setting ADD COLUMN max_session_minutes INTEGER;
+
+-- ギルドの設定による予約（NULLはユーザーの予約。/cancel, /clearの対象外にする）
+ALTER TABLE reservation ADD COLUMN policy TEXT;
+UPDATE reservation SET policy='QuietHours' WHERE id IN (SELECT quiet_reservation_id FROM setting);
+
+-- 通話中のユーザーの参加時刻（再起動後も連続通話時間を引き継ぐ）
+CREATE TABLE IF NOT EXISTS voice_session (
+    user_id BIGINT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    started_at DATETIME NOT NULL,
+    reservation_id INTEGER REFERENCES reservation(id) ON DELETE SET NULL,
+    PRIMARY KEY (user_id, guild_id)
+);
@@ -0,0 +1,11 @@
+-- ユーザーが自分で決める1日の通話時間の上限（ユーザーのタイムゾーンの0時にリセット）
+CREATE TABLE IF NOT EXISTS voice_budget (
+    user_id BIGINT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    daily_minutes INTEGER NOT NULL,
+    -- dayの日付に使った通話時間（秒）
+    used_seconds INTEGER NOT NULL DEFAULT 0,
+    day TEXT NOT NULL DEFAULT '',
+    reservation_id INTEGER REFERENCES reservation(id) ON DELETE SET NULL,
+    PRIMARY KEY (user_id, guild_id)
+);
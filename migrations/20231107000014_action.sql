@@ -0,0 +1,10 @@
+-- 切断時の動作（Disconnect: 切断, Move: action_channel_idへ移動, Afk: AFKチャンネルへ移動）
+ALTER TABLE setting ADD COLUMN action TEXT NOT NULL DEFAULT 'Disconnect';
+ALTER TABLE setting ADD COLUMN action_channel_id BIGINT;
+
+-- NULLならギルドの設定に従う
+ALTER TABLE reservation ADD COLUMN action TEXT;
+ALTER TABLE reservation ADD COLUMN action_channel_id BIGINT;
+
+-- 移動先のチャンネルはロックアウト中も参加できる
+ALTER TABLE lockout ADD COLUMN channel_id BIGINT;
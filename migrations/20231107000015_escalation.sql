@@ -0,0 +1,12 @@
+-- 期限にサーバーミュート、2分後にスピーカーミュート、5分後に切断
+ALTER TABLE reservation ADD COLUMN escalate BOOLEAN NOT NULL DEFAULT 0;
+
+-- 段階的な切断でミュートしたユーザー（reservation_idがNULLになったら次の参加時に解除する）
+CREATE TABLE IF NOT EXISTS muted_member (
+    user_id BIGINT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    reservation_id INTEGER REFERENCES reservation(id) ON DELETE SET NULL,
+    mute BOOLEAN NOT NULL DEFAULT 0,
+    deaf BOOLEAN NOT NULL DEFAULT 0,
+    PRIMARY KEY (user_id, guild_id)
+);
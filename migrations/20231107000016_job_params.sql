@@ -0,0 +1,2 @@
+-- ジョブの処理ごとのパラメータ（形式は各処理が決める）
+ALTER TABLE job ADD COLUMN params TEXT;
@@ -0,0 +1,2 @@
+-- 相対指定で予約できる最大の時間（日）
+ALTER TABLE setting ADD COLUMN max_horizon_days INTEGER NOT NULL DEFAULT 30;
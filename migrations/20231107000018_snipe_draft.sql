@@ -0,0 +1,16 @@
+-- /snipeで指定方法のボタンを押すまでの入力（ボタンのcustom_idにidを含め、再起動後も選択できるようにする）
+CREATE TABLE IF NOT EXISTS snipe_draft (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id BIGINT NOT NULL,
+    guild_id BIGINT NOT NULL,
+    time TEXT NOT NULL,
+    recurrence TEXT,
+    warnings TEXT,
+    lockout TEXT,
+    action TEXT,
+    action_channel_id BIGINT,
+    escalate BOOLEAN NOT NULL DEFAULT 0,
+    -- Target::parseで読み戻せる形式をカンマ区切りで保存
+    targets TEXT NOT NULL,
+    created_at DATETIME NOT NULL
+);
@@ -0,0 +1,3 @@
+-- 予約の理由（/scheduleで入力し、/showに表示する）
+ALTER TABLE reservation ADD COLUMN reason TEXT;
+ALTER TABLE snipe_draft ADD COLUMN reason TEXT;
@@ -0,0 +1,2 @@
+-- 今回の発生の本来の時刻（延長や/rescheduleでずらしても変わらない。繰り返しの次回はここから求める）
+ALTER TABLE reservation ADD COLUMN scheduled_utc DATETIME;
@@ -0,0 +1,2 @@
+-- 通話中であることを最後に確認した時刻（Bot停止中に抜けたユーザーの使用時間はここまでとする）
+ALTER TABLE voice_session ADD COLUMN seen_at DATETIME;
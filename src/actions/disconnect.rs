@@ -0,0 +1,79 @@
+use serenity::async_trait;
+use chrono::{Duration, NaiveDateTime};
+use crate::SqliteDatabase;
+use crate::voice;
+use crate::job::{Job, Target};
+use super::{JobAction, JobContext, Outcome, outcome};
+
+// 通話から切断する（ギルド/予約の設定によっては移動する）
+pub struct Disconnect;
+
+impl Disconnect {
+    // job.event_typeに保存する名前
+    pub const NAME: &'static str = "Disconnect";
+}
+
+#[async_trait]
+impl JobAction for Disconnect {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    // 延長回数を戻し、次回の切断予約と切断前通知を登録する
+    async fn schedule_next(&self, database: &SqliteDatabase, tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+                           job: &Job, next: NaiveDateTime) -> Result<(), sqlx::Error> {
+        SqliteDatabase::reset_snooze(tx, job.reservation_id).await?;
+        database.insert_disconnect_jobs_in(tx, job.reservation_id, next, job.userid(), job.guildid()).await
+    }
+
+    async fn execute(&self, job: &JobContext<'_>) -> Result<Outcome, String> {
+        let (ctx, database) = (job.ctx, job.database);
+
+        // 切断後のロックアウト（ユーザー指定の予約は通話中でなくても適用する）
+        let lockout_until = job.reservation.as_ref()
+            .and_then(|r| r.lockout_minutes)
+            .map(|minutes| job.job.naive_utc + Duration::minutes(minutes));
+        if let Some(until) = lockout_until {
+            let mut locked_ids = job.user_ids.clone();
+            if let Target::User(u) = job.target {
+                if !locked_ids.contains(&u) {
+                    locked_ids.push(u);
+                }
+            }
+            for &user_id in locked_ids.iter() {
+                database.insert_lockout(job.job.reservation_id, user_id, job.job.guildid(), until, job.destination)
+                    .await
+                    .map_err(|why| why.to_string())?;
+            }
+        }
+
+        let mut errors = Vec::new();
+        for &user_id in job.user_ids.iter() {
+            let result = match job.destination {
+                Some(channel_id) => job.job.move_to(ctx, user_id, channel_id).await,
+                None => job.job.disconnect(ctx, user_id).await
+            };
+            if let Err(why) = result {
+                errors.push(format!("{:?} {}: {}", job.action, user_id, why));
+                continue;
+            }
+            let mut content = format!("<t:{0}:d> <t:{0}:T>に{1}しました", job.job.timestamp(), job.action.describe_in(&job.guild));
+            if let Some(until) = lockout_until {
+                content += &format!("\n<t:{0}:d> <t:{0}:T>まで通話に参加できません", until.and_utc().timestamp());
+            }
+            let msg = job.job.direct_message(ctx, user_id, |m| m.content(content)).await;
+            if let Err(why) = msg {
+                errors.push(format!("direct message {}: {}", user_id, why));
+            }
+        }
+
+        // 段階的な切断が終わったのでミュートを解除する
+        // （予約の読み込みに失敗しても残らないよう、段階的な切断かどうかに関わらず予約IDで解除する）
+        database.release_reservation_mutes(job.job.reservation_id)
+            .await
+            .map_err(|why| why.to_string())?;
+        voice::release_mutes(ctx, database, job.job.guildid()).await;
+
+        outcome(job.user_ids.len(), errors)
+    }
+}
@@ -0,0 +1,162 @@
+pub mod disconnect;
+pub mod notification;
+pub mod mute;
+pub mod reminder;
+
+use serenity::{
+    async_trait,
+    CacheAndHttp,
+    model::{
+        guild::Guild,
+        id::{ChannelId, UserId}
+    }
+};
+use std::{collections::HashMap, sync::Arc};
+use crate::SqliteDatabase;
+use chrono::NaiveDateTime;
+use crate::job::{Job, Reservation, Target, Action};
+
+// ジョブの実行時点の予約/設定/ボイス状態
+pub struct JobContext<'a> {
+    pub ctx: &'a CacheAndHttp,
+    pub database: &'a Arc<SqliteDatabase>,
+    pub job: &'a Job,
+    pub guild: Guild,
+    pub reservation: Option<Reservation>,
+    pub target: Target,
+    pub action: Action,
+    // 移動先のチャンネル（Noneなら切断）
+    pub destination: Option<ChannelId>,
+    // 通話中の対象ユーザー（Botと既に移動先にいるユーザーは除く）
+    pub user_ids: Vec<UserId>,
+}
+
+impl<'a> JobContext<'a> {
+    pub async fn new(ctx: &'a CacheAndHttp, database: &'a Arc<SqliteDatabase>, job: &'a Job)
+                     -> Result<JobContext<'a>, String> {
+        let reservation = database.get_reservation(job.reservation_id).await
+            .map_err(|why| why.to_string())?;
+        let target = reservation.as_ref().map_or(Target::User(job.userid()), |r| r.target());
+
+        let guild = ctx.cache.guild(job.guildid())
+            .ok_or_else(|| format!("guild {} is not cached", job.guild_id))?;
+        let setting = database.get_guild_setting(job.guildid()).await
+            .map_err(|why| why.to_string())?;
+        let action = reservation.as_ref().map_or(setting.action(), |r| r.action(&setting));
+        let destination = action.destination(&guild);
+
+        let bot_id = ctx.cache.current_user_id();
+        let user_ids = target.resolve(ctx, &guild).await
+            .into_iter()
+            .filter(|&u| u != bot_id)
+            .filter(|u| destination.is_none()
+                    || guild.voice_states.get(u).and_then(|v| v.channel_id) != destination)
+            .collect();
+
+        Ok(JobContext { ctx, database, job, guild, reservation, target, action, destination, user_ids })
+    }
+
+    pub fn escalate(&self) -> bool {
+        self.reservation.as_ref().is_some_and(|r| r.escalate)
+    }
+}
+
+// ジョブの種類ごとの処理（job.event_typeの名前で登録し、job.paramsは各処理が解釈する）
+#[async_trait]
+pub trait JobAction: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn execute(&self, job: &JobContext<'_>) -> Result<Outcome, String>;
+
+    // 繰り返し予約でこのジョブが過ぎたときに次回分を登録する（既定では何もしない）
+    // 取り出したジョブの削除と同じトランザクションで登録する
+    async fn schedule_next(&self, _database: &SqliteDatabase, _tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+                           _job: &Job, _next: NaiveDateTime) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+}
+
+// ジョブの実行結果（対象が通話中でなく何もしなかった場合はSkipped）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Executed,
+    Skipped,
+}
+
+// 対象ごとのエラーをまとめる
+pub fn outcome(targets: usize, errors: Vec<String>) -> Result<Outcome, String> {
+    match (targets, errors.is_empty()) {
+        (0, _) => Ok(Outcome::Skipped),
+        (_, true) => Ok(Outcome::Executed),
+        (_, false) => Err(errors.join(", "))
+    }
+}
+
+pub struct ActionRegistry {
+    actions: HashMap<&'static str, Box<dyn JobAction>>,
+    // 以前のバージョンで保存されたevent_typeの読み替え
+    aliases: HashMap<&'static str, &'static str>,
+}
+
+impl ActionRegistry {
+    pub fn register<A: JobAction + 'static>(&mut self, action: A) -> &mut Self {
+        self.actions.insert(action.name(), Box::new(action));
+        self
+    }
+
+    pub fn alias(&mut self, legacy: &'static str, name: &'static str) -> &mut Self {
+        self.aliases.insert(legacy, name);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn JobAction> {
+        let name = self.aliases.get(name).copied().unwrap_or(name);
+        self.actions.get(name).map(|action| action.as_ref())
+    }
+}
+
+impl Default for ActionRegistry {
+    fn default() -> Self {
+        let mut registry = ActionRegistry { actions: HashMap::new(), aliases: HashMap::new() };
+        registry
+            .register(disconnect::Disconnect)
+            .register(notification::Notification)
+            .register(mute::Mute)
+            .register(mute::Deafen)
+            .register(reminder::Reminder)
+            .alias("Notification3Min", notification::Notification::NAME);
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::ESCALATION;
+
+    #[test]
+    fn registry_resolves_stored_names() {
+        let registry = ActionRegistry::default();
+        for name in [disconnect::Disconnect::NAME, notification::Notification::NAME,
+                     mute::Mute::NAME, mute::Deafen::NAME, reminder::Reminder::NAME] {
+            assert_eq!(registry.get(name).map(|a| a.name()), Some(name));
+        }
+        // 段階的な切断の各段階も登録済みの処理
+        assert!(ESCALATION.iter().all(|&(name, _)| registry.get(name).is_some()));
+    }
+
+    #[test]
+    fn registry_rejects_unknown_names() {
+        let registry = ActionRegistry::default();
+        assert!(registry.get("Kick").is_none());
+        assert!(registry.get("").is_none());
+        assert!(registry.get("disconnect").is_none());
+    }
+
+    #[test]
+    fn registry_resolves_legacy_alias() {
+        let registry = ActionRegistry::default();
+        let action = registry.get("Notification3Min").unwrap();
+        assert_eq!(action.name(), notification::Notification::NAME);
+    }
+}
@@ -0,0 +1,77 @@
+use serenity::async_trait;
+use super::{JobAction, JobContext, Outcome, outcome};
+
+// 段階的な切断のサーバーミュート
+pub struct Mute;
+
+// 段階的な切断のスピーカーミュート
+pub struct Deafen;
+
+impl Mute {
+    // job.event_typeに保存する名前
+    pub const NAME: &'static str = "Mute";
+}
+
+impl Deafen {
+    pub const NAME: &'static str = "Deafen";
+}
+
+#[async_trait]
+impl JobAction for Mute {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    async fn execute(&self, job: &JobContext<'_>) -> Result<Outcome, String> {
+        silence(job, true, false).await
+    }
+}
+
+#[async_trait]
+impl JobAction for Deafen {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    async fn execute(&self, job: &JobContext<'_>) -> Result<Outcome, String> {
+        silence(job, false, true).await
+    }
+}
+
+async fn silence(job: &JobContext<'_>, mute: bool, deaf: bool) -> Result<Outcome, String> {
+    let (ctx, database) = (job.ctx, job.database);
+    let disconnect_at = database.get_job_time(job.job.reservation_id, super::disconnect::Disconnect::NAME)
+        .await
+        .map_err(|why| why.to_string())?
+        .unwrap_or(job.job.naive_utc);
+
+    let mut errors = Vec::new();
+    for &user_id in job.user_ids.iter() {
+        let result = job.job.guildid().edit_member(&ctx.http, user_id, |m| {
+            if mute {
+                m.mute(true);
+            }
+            if deaf {
+                m.deafen(true);
+            }
+            m
+        }).await;
+        if let Err(why) = result {
+            errors.push(format!("{} {}: {}", job.job.event_type, user_id, why));
+            continue;
+        }
+        database.upsert_muted_member(user_id, job.job.guildid(), job.job.reservation_id, mute, deaf)
+            .await
+            .map_err(|why| why.to_string())?;
+
+        let stage = if mute { "サーバーミュート" } else { "スピーカーミュート" };
+        let content = format!("期限を過ぎたため{0}しました\n<t:{1}:T> (<t:{1}:R>) に{2}します",
+                              stage, disconnect_at.and_utc().timestamp(), job.action.describe_in(&job.guild));
+        let msg = job.job.direct_message(ctx, user_id, |m| m.content(content)).await;
+        if let Err(why) = msg {
+            errors.push(format!("direct message {}: {}", user_id, why));
+        }
+    }
+
+    outcome(job.user_ids.len(), errors)
+}
@@ -0,0 +1,200 @@
+use serenity::{
+    async_trait,
+    model::{
+        id::{GuildId, UserId},
+        application::interaction::{
+            message_component::MessageComponentInteraction,
+            InteractionResponseType
+        }
+    },
+    prelude::*
+};
+use chrono::{Utc, Duration, NaiveDateTime};
+use std::sync::Arc;
+use crate::SqliteDatabase;
+use crate::voice;
+use crate::job::{Target, Reservation, GuildSetting, escalation_minutes};
+use crate::schedule::describe_duration;
+use crate::commands::utils::SnoozeAction;
+use super::{JobAction, JobContext, Outcome, outcome};
+
+// 切断前の通知DM（延長/今すぐ切断のボタン付き）
+pub struct Notification;
+
+impl Notification {
+    // job.event_typeに保存する名前
+    pub const NAME: &'static str = "Notification";
+}
+
+#[async_trait]
+impl JobAction for Notification {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    async fn execute(&self, job: &JobContext<'_>) -> Result<Outcome, String> {
+        let (ctx, database) = (job.ctx, job.database);
+
+        // 予約変更にも追従するよう、期限は通知時に取得する
+        let disconnect_at = match database.get_deadline(job.job.reservation_id).await {
+            Ok(Some(t)) => t,
+            Ok(None) => return Ok(Outcome::Skipped),
+            Err(why) => return Err(why.to_string())
+        };
+        let (count_left, minutes_left) = snooze_left(database, job.job.reservation_id, job.job.guildid())
+            .await
+            .map_err(|why| why.to_string())?;
+        let enforcement = describe_enforcement(&job.action.describe_in(&job.guild), job.escalate());
+
+        // 延長/今すぐ切断のボタンはユーザー指定の予約の本人か予約者にだけ付ける
+        // （チャンネル/ロール/サーバー指定では他のメンバーが全員の切断を延長できてしまう）
+        let owner_id = job.reservation.as_ref().map_or(job.job.userid(), |r| r.userid());
+        let mut errors = Vec::new();
+        for &user_id in job.user_ids.iter() {
+            let buttons = matches!(job.target, Target::User(_)) || user_id == owner_id;
+            let msg = job.job.direct_message(ctx, user_id, |m| {
+                m.content(warning_content(disconnect_at, &enforcement, count_left, minutes_left));
+                if buttons {
+                    m.components(|c| c.add_action_row(
+                        SnoozeAction::action_row(job.job.reservation_id, count_left, minutes_left)));
+                }
+                m
+            }).await;
+
+            if let Err(why) = msg {
+                errors.push(format!("direct message {}: {}", user_id, why));
+            }
+        }
+
+        outcome(job.user_ids.len(), errors)
+    }
+}
+
+// 期限に行う処理の説明（段階的な切断ではミュートから始める）
+fn describe_enforcement(action: &str, escalate: bool) -> String {
+    if !escalate {
+        return action.to_string();
+    }
+    format!("サーバーミュートし、その{}分後に{}", escalation_minutes(super::disconnect::Disconnect::NAME), action)
+}
+
+fn warning_content(disconnect_at: NaiveDateTime, enforcement: &str, count_left: i64, minutes_left: i64) -> String {
+    let remaining = describe_duration(disconnect_at - Utc::now().naive_utc());
+    format!("{0}後 (<t:{1}:T>) に{2}します\n延長できる残り: {3}回 / {4}分",
+            remaining, disconnect_at.and_utc().timestamp(), enforcement, count_left, minutes_left)
+}
+
+async fn snooze_left(database: &SqliteDatabase, reservation_id: i64, guild_id: GuildId)
+                     -> Result<(i64, i64), sqlx::Error> {
+    let setting = database.get_guild_setting(guild_id).await?;
+    Ok(match database.get_reservation(reservation_id).await? {
+        Some(reservation) => reservation.snooze_left(&setting),
+        None => (0, 0)
+    })
+}
+
+// 通知DMのボタン（custom_idの予約のidから処理するので、再起動後に押されても受け付ける）
+pub async fn press(ctx: Arc<Context>, component: &MessageComponentInteraction) {
+    let Some((reservation_id, action)) = SnoozeAction::from_custom_id(&component.data.custom_id) else {
+        return;
+    };
+    let database = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<SqliteDatabase>().unwrap().clone()
+    };
+
+    let (content, buttons) = apply(&ctx, &database, reservation_id, component.user.id, action)
+        .await
+        .unwrap_or_else(|why| {
+            println!("snooze failed: {}", why);
+            ("延長に失敗しました".to_string(), None)
+        });
+
+    component.create_interaction_response(&ctx.http, |response| {
+        response
+            .kind(InteractionResponseType::UpdateMessage)
+            .interaction_response_data(|message| {
+                message
+                    .content(content)
+                    .components(|c| match buttons {
+                        Some((count_left, minutes_left)) =>
+                            c.add_action_row(SnoozeAction::action_row(reservation_id, count_left, minutes_left)),
+                        None => c
+                    })
+            })
+    })
+    .await
+    .unwrap_or_else(|why| println!("cannot respond to button: {}", why));
+}
+
+// ボタンの処理後のメッセージと、ボタンを残すなら残りの延長回数/分を返す
+async fn apply(ctx: &Context, database: &SqliteDatabase, reservation_id: i64, user_id: UserId,
+               pressed: SnoozeAction) -> Result<(String, Option<(i64, i64)>), sqlx::Error> {
+    // 切断済み/取り消し済みの予約ではボタンを外す
+    let (Some(reservation), Some(disconnect_at)) = (database.get_reservation(reservation_id).await?,
+                                                   database.get_deadline(reservation_id).await?) else {
+        return Ok(("この予約はすでに終了しています".to_string(), None));
+    };
+    // ボタンを付けるのは予約者（ユーザー指定では本人）だけ
+    if user_id != reservation.userid() {
+        return Ok(("この予約は変更できません".to_string(), None));
+    }
+    let setting = database.get_guild_setting(reservation.guildid()).await?;
+    let action = reservation.action(&setting);
+    let guild = ctx.cache.guild(reservation.guildid());
+    let description = guild.as_ref().map_or_else(|| action.describe(), |guild| action.describe_in(guild));
+    let enforcement = describe_enforcement(&description, reservation.escalate);
+
+    match (pressed, reservation.target()) {
+        (SnoozeAction::Extend(minutes), _) =>
+            snooze(ctx, database, &reservation, &setting, disconnect_at, &enforcement, minutes).await,
+        (SnoozeAction::DisconnectNow, Target::User(_)) => {
+            database.reschedule_disconnect_jobs(reservation_id, Utc::now().naive_utc(),
+                                                reservation.userid(), reservation.guildid()).await?;
+            Ok((format!("{}します", enforcement), None))
+        },
+        // チャンネル/ロール/サーバー指定では押したユーザーだけを切断し、予約は残す
+        (SnoozeAction::DisconnectNow, _) => {
+            let guild_id = reservation.guildid();
+            let destination = guild.and_then(|guild| action.destination(&guild));
+            let result = match destination {
+                Some(channel_id) => guild_id.move_member(&ctx.http, user_id, channel_id).await,
+                None => guild_id.disconnect_member(&ctx.http, user_id).await
+            };
+            let content = match result {
+                Ok(_) => format!("{}しました", description),
+                Err(why) => {
+                    println!("{:?} {}: {}", action, user_id, why);
+                    format!("{}できませんでした", description)
+                }
+            };
+            Ok((content, None))
+        }
+    }
+}
+
+// 切断時刻をずらし、延長後のメッセージと残りの延長回数/分を返す
+async fn snooze(ctx: &Context, database: &SqliteDatabase, reservation: &Reservation, setting: &GuildSetting,
+                disconnect_at: NaiveDateTime, enforcement: &str, minutes: i64)
+                -> Result<(String, Option<(i64, i64)>), sqlx::Error> {
+    if !database.add_snooze(reservation.id, minutes, setting).await? {
+        let (count_left, minutes_left) = snooze_left(database, reservation.id, reservation.guildid()).await?;
+        let content = format!("{}\nこれ以上延長できません",
+                              warning_content(disconnect_at, enforcement, count_left, minutes_left));
+        return Ok((content, Some((count_left, minutes_left))));
+    }
+
+    let target_datetime = disconnect_at + Duration::minutes(minutes);
+    database.reschedule_disconnect_jobs(reservation.id, target_datetime,
+                                        reservation.userid(), reservation.guildid()).await?;
+    // 段階的な切断でミュート済みなら、延長した期限まで解除する
+    if reservation.escalate {
+        database.release_reservation_mutes(reservation.id).await?;
+        voice::release_mutes(ctx, database, reservation.guildid()).await;
+    }
+
+    let (count_left, minutes_left) = reservation.snooze_left(setting);
+    let left = (count_left - 1, minutes_left - minutes);
+    let content = format!("{}分延長しました\n{}", minutes, warning_content(target_datetime, enforcement, left.0, left.1));
+    Ok((content, Some(left)))
+}
@@ -0,0 +1,105 @@
+use serenity::async_trait;
+use chrono::NaiveDateTime;
+use crate::SqliteDatabase;
+use crate::job::Job;
+use super::{JobAction, JobContext, Outcome, outcome};
+
+// 切断せずにDMで知らせるだけの予約
+pub struct Reminder;
+
+impl Reminder {
+    // job.event_typeに保存する名前
+    pub const NAME: &'static str = "Reminder";
+}
+
+// job.paramsの形式: "<voice|always>:<メッセージ>"
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReminderParams {
+    // 通話中でなくても通知する
+    pub always: bool,
+    pub message: Option<String>,
+}
+
+impl ReminderParams {
+    // 不正な値は既定（通話中のみ、メッセージなし）として扱う
+    pub fn parse(params: Option<&str>) -> Self {
+        let Some((mode, message)) = params.and_then(|p| p.split_once(':')) else {
+            return Self::default();
+        };
+        let message = Some(message.to_string()).filter(|m| !m.is_empty());
+        ReminderParams { always: mode == "always", message }
+    }
+
+    pub fn to_params(&self) -> String {
+        let mode = if self.always { "always" } else { "voice" };
+        format!("{}:{}", mode, self.message.as_deref().unwrap_or_default())
+    }
+
+    pub fn message(&self) -> &str {
+        self.message.as_deref().unwrap_or("予定の時刻になりました")
+    }
+}
+
+#[async_trait]
+impl JobAction for Reminder {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    // 同じメッセージで次回のリマインダーを登録する
+    async fn schedule_next(&self, _database: &SqliteDatabase, tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+                           job: &Job, next: NaiveDateTime) -> Result<(), sqlx::Error> {
+        SqliteDatabase::insert_action_job_in(tx, job.reservation_id, next, job.userid(), job.guildid(),
+                                             Self::NAME, job.params.as_deref()).await?;
+        Ok(())
+    }
+
+    async fn execute(&self, job: &JobContext<'_>) -> Result<Outcome, String> {
+        let ctx = job.ctx;
+        let params = ReminderParams::parse(job.job.params.as_deref());
+
+        // 移動先にいるかどうかは関係ないので、対象を改めて求める
+        let bot_id = ctx.cache.current_user_id();
+        let user_ids: Vec<_> = match params.always {
+            true => job.target.members(ctx, &job.guild).await,
+            false => job.target.resolve(ctx, &job.guild).await
+        }.into_iter().filter(|&u| u != bot_id).collect();
+
+        let content = format!("🔔 <t:{0}:T> のリマインダー\n{1}", job.job.timestamp(), params.message());
+        let mut errors = Vec::new();
+        for &user_id in user_ids.iter() {
+            let msg = job.job.direct_message(ctx, user_id, |m| m.content(&content)).await;
+            if let Err(why) = msg {
+                errors.push(format!("direct message {}: {}", user_id, why));
+            }
+        }
+
+        outcome(user_ids.len(), errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_round_trip() {
+        let params = ReminderParams { always: true, message: Some("宿題: 数学".to_string()) };
+        let parsed = ReminderParams::parse(Some(&params.to_params()));
+        assert!(parsed.always);
+        assert_eq!(parsed.message.as_deref(), Some("宿題: 数学"));
+
+        let parsed = ReminderParams::parse(Some(&ReminderParams::default().to_params()));
+        assert!(!parsed.always);
+        assert_eq!(parsed.message(), "予定の時刻になりました");
+    }
+
+    #[test]
+    fn params_fall_back_to_defaults() {
+        for params in [None, Some(""), Some("always")] {
+            let parsed = ReminderParams::parse(params);
+            assert!(!parsed.always);
+            assert_eq!(parsed.message, None);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use serenity::{
+    builder::CreateApplicationCommand,
+    model::{
+        id::{GuildId, UserId},
+        application::{
+            command::CommandOptionType,
+            interaction::{
+                application_command::ApplicationCommandInteraction,
+                InteractionResponseType
+            },
+        },
+    },
+    prelude::*
+};
+use std::sync::Arc;
+use chrono::{Utc, Duration};
+use crate::SqliteDatabase;
+use crate::schedule::describe_duration;
+use crate::voice::{budget_used, schedule_budget};
+use crate::commands::utils::{int_option_ref, bool_option_ref};
+
+// 通話中なら切断予約も新しい上限で作り直す
+async fn set_budget(database: &SqliteDatabase, user_id: UserId, guild_id: GuildId, minutes: i64)
+                    -> Result<String, sqlx::Error> {
+    database.upsert_voice_budget(user_id, guild_id, minutes).await?;
+    if let Some(session) = database.get_voice_session(user_id, guild_id).await? {
+        schedule_budget(database, &session, guild_id).await?;
+    }
+
+    Ok(format!("1日の通話時間を「{}」に設定しました\n{}",
+               describe_duration(Duration::minutes(minutes)),
+               get_budget(database, user_id, guild_id).await?))
+}
+
+async fn get_budget(database: &SqliteDatabase, user_id: UserId, guild_id: GuildId)
+                    -> Result<String, sqlx::Error> {
+    let Some(budget) = database.get_voice_budget(user_id, guild_id).await? else {
+        return Ok("1日の通話時間は設定されていません".to_string());
+    };
+
+    let tz = database.get_timezone(user_id, guild_id).await?;
+    let session = database.get_voice_session(user_id, guild_id).await?;
+    let used = budget_used(&budget, session.as_ref(), tz, Utc::now().naive_utc());
+    let remaining = (budget.daily() - used).max(Duration::zero());
+
+    Ok(format!("今日の残り: {} (使用 {} / {})\nタイムゾーン: {} (0時にリセット)",
+               describe_duration(remaining), describe_duration(used),
+               describe_duration(budget.daily()), tz.name()))
+}
+
+pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.unwrap();
+    let user_id = command.user.id;
+    let database = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<SqliteDatabase>().unwrap().clone()
+    };
+
+    let options = &command.data.options;
+    let result = if bool_option_ref(options, "off") == Some(&true) {
+        database.delete_voice_budget(user_id, guild_id).await
+            .map(|_| "1日の通話時間の上限を解除しました".to_string())
+    } else if let Some(&minutes) = int_option_ref(options, "minutes") {
+        set_budget(&database, user_id, guild_id, minutes).await
+    } else {
+        get_budget(&database, user_id, guild_id).await
+    };
+
+    let content = result.unwrap_or_else(|why| {
+        println!("{:?}", why);
+        "1日の通話時間の設定に失敗しました".to_string()
+    });
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message|
+                    message.content(content)
+                )
+        })
+        .await
+        .unwrap_or_else(|why| println!("cannot respond to slash command: {}", why));
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command.name("budget").description("1日に通話できる時間の上限を設定/表示します")
+        .create_option(|option| {
+            option
+                .name("minutes")
+                .description("1日の上限 (分, 例: 120)")
+                .kind(CommandOptionType::Integer)
+                .min_int_value(10)
+                .max_int_value(24 * 60)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("off")
+                .description("上限を解除します")
+                .kind(CommandOptionType::Boolean)
+                .required(false)
+        })
+}
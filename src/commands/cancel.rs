@@ -0,0 +1,112 @@
+use serenity::{
+    builder::CreateApplicationCommand,
+    model::{
+        application::{
+            command::CommandOptionType,
+            interaction::{
+                application_command::ApplicationCommandInteraction,
+                autocomplete::AutocompleteInteraction,
+                InteractionResponseType
+            },
+        },
+    },
+    prelude::*
+};
+use std::sync::Arc;
+use crate::{SqliteDatabase, voice};
+use crate::actions::reminder::Reminder;
+use crate::commands::utils::int_option_ref;
+
+pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.unwrap();
+    let user_id = command.user.id;
+    let database = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<SqliteDatabase>().unwrap().clone()
+    };
+
+    let reservation_id = *int_option_ref(&command.data.options, "id").unwrap();
+    let reservation = database.get_user_reservations(user_id, guild_id).await
+        .unwrap()
+        .into_iter()
+        .find(|job| job.reservation_id == reservation_id);
+
+    let content = match reservation {
+        Some(job) => {
+            match database.delete_reservation(reservation_id, user_id, guild_id).await {
+                Ok(_) => {
+                    // 予約でミュート中のユーザーを解除する
+                    voice::release_mutes(&ctx, &database, guild_id).await;
+                    let kind = if job.is(Reminder::NAME) { "リマインダー" } else { "切断予約" };
+                    format!("<t:{0}:d> <t:{0}:T>の{2} (#{1}) を削除しました",
+                            job.timestamp(), reservation_id, kind)
+                },
+                Err(why) => {
+                    println!("{:?}", why);
+                    "切断予約の削除に失敗しました".to_string()
+                }
+            }
+        },
+        None => format!("切断予約 #{} が見つかりません", reservation_id)
+    };
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message|
+                    message.content(content)
+                )
+        })
+        .await
+        .unwrap_or_else(|why| println!("cannot respond to slash command: {}", why));
+}
+
+pub async fn autocomplete(ctx: Arc<Context>, autocomplete: &AutocompleteInteraction) {
+    let guild_id = autocomplete.guild_id.unwrap();
+    let user_id = autocomplete.user.id;
+    let database = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<SqliteDatabase>().unwrap().clone()
+    };
+
+    let input = autocomplete.data.options
+        .iter()
+        .find(|option| option.focused)
+        .and_then(|option| option.value.as_ref())
+        .map(|value| value.to_string().trim_matches('"').to_owned())
+        .unwrap_or_default();
+
+    let tz = database.get_timezone(user_id, guild_id).await.unwrap();
+    let jobs = database.get_user_reservations(user_id, guild_id).await.unwrap();
+
+    autocomplete
+        .create_autocomplete_response(&ctx.http, |response| {
+            jobs.iter()
+                .map(|job| {
+                    let local = job.naive_utc.and_utc().with_timezone(&tz);
+                    (job.reservation_id, format!("{}#{} {} ({})",
+                                                 if job.is(Reminder::NAME) { "🔔" } else { "" },
+                                                 job.reservation_id,
+                                                 local.format("%m/%d %H:%M"),
+                                                 tz.name()))
+                })
+                .filter(|(_, name)| name.contains(input.as_str()))
+                .take(25)
+                .fold(response, |r, (id, name)| r.add_int_choice(name, id))
+        })
+        .await
+        .unwrap_or_else(|why| println!("cannot respond to autocomplete: {}", why));
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command.name("cancel").description("通話の切断予約を1件削除します")
+        .create_option(|option| {
+            option
+                .name("id")
+                .description("削除する予約")
+                .kind(CommandOptionType::Integer)
+                .set_autocomplete(true)
+                .required(true)
+        })
+}
@@ -10,7 +10,7 @@ use serenity::{
     prelude::*
 };
 use std::sync::Arc;
-use crate::SqliteDatabase;
+use crate::{SqliteDatabase, voice};
 
 pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
     let guild_id = command.guild_id.unwrap();
@@ -21,6 +21,7 @@ pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
     };
 
     database.delete_guild_jobs(user_id, guild_id).await.unwrap();
+    voice::release_mutes(&ctx, &database, guild_id).await;
 
     command
         .create_interaction_response(&ctx.http, |response| {
@@ -0,0 +1,436 @@
+use serenity::{
+    builder::CreateApplicationCommand,
+    model::{
+        id::{GuildId, UserId, RoleId},
+        permissions::Permissions,
+        channel::ChannelType,
+        application::{
+            command::CommandOptionType,
+            interaction::{
+                application_command::{ApplicationCommandInteraction, CommandDataOption},
+                InteractionResponseType
+            },
+        },
+    },
+    prelude::*
+};
+use std::sync::Arc;
+use chrono::{Utc, Duration, NaiveTime};
+use crate::SqliteDatabase;
+use crate::job::{GuildSetting, Target, ReservationOptions, Policy, Action};
+use crate::schedule::{LeadTimes, QuietHours, Recurrence, Repeat, Schedule, describe_duration};
+use crate::voice::reschedule_sessions;
+use crate::time_expr::TimeExpr;
+use crate::commands::utils::{string_option_ref, int_option_ref, bool_option_ref, role_option_ref,
+                             action_option, describe_timezone};
+
+async fn set_warnings(database: Arc<SqliteDatabase>, guild_id: GuildId, input: &str) -> String {
+    let lead_times = match LeadTimes::parse(input) {
+        Ok(l) => l,
+        Err(why) => return why
+    };
+
+    if database.update_guild_warnings(guild_id, &lead_times).await.is_ok() {
+        format!("切断前通知を「{}」に設定しました", lead_times.describe())
+    } else {
+        "切断前通知の設定に失敗しました".to_string()
+    }
+}
+
+async fn get_warnings(database: Arc<SqliteDatabase>, guild_id: GuildId) -> String {
+    if let Ok(setting) = database.get_guild_setting(guild_id).await {
+        format!("切断前通知は「{}」に設定されています", setting.lead_times().describe())
+    } else {
+        "切断前通知の取得に失敗しました".to_string()
+    }
+}
+
+// 未指定の項目は現在の設定を引き継ぐ
+async fn set_snooze(database: Arc<SqliteDatabase>, guild_id: GuildId,
+                    count: Option<i64>, minutes: Option<i64>) -> String {
+    let Ok(setting) = database.get_guild_setting(guild_id).await else {
+        return "延長の上限の取得に失敗しました".to_string();
+    };
+    let count = count.unwrap_or(setting.snooze_count);
+    let minutes = minutes.unwrap_or(setting.snooze_minutes);
+
+    if database.update_guild_snooze(guild_id, count, minutes).await.is_ok() {
+        format!("切断の延長を「{}回 / 合計{}分まで」に設定しました", count, minutes)
+    } else {
+        "延長の上限の設定に失敗しました".to_string()
+    }
+}
+
+async fn get_snooze(database: Arc<SqliteDatabase>, guild_id: GuildId) -> String {
+    if let Ok(setting) = database.get_guild_setting(guild_id).await {
+        format!("切断の延長は「{}回 / 合計{}分まで」に設定されています",
+                setting.snooze_count, setting.snooze_minutes)
+    } else {
+        "延長の上限の取得に失敗しました".to_string()
+    }
+}
+
+fn parse_local_time(input: &str) -> Option<NaiveTime> {
+    TimeExpr::parse(input).ok()?.time_of_day()
+}
+
+// 静粛時間の開始時刻に切断する毎日の予約を削除
+async fn clear_quiet_reservation(database: &SqliteDatabase, setting: &GuildSetting, guild_id: GuildId)
+                                 -> Result<(), sqlx::Error> {
+    let Some(reservation_id) = setting.quiet_reservation_id else {
+        return Ok(());
+    };
+    if let Some(reservation) = database.get_reservation(reservation_id).await? {
+        database.delete_reservation(reservation_id, UserId::from(reservation.user_id as u64), guild_id).await?;
+    }
+    Ok(())
+}
+
+// 開始時刻の切断（切断前通知を含む）は毎日の予約で行い、時間帯中の再参加はvoice_state_updateで切断する
+async fn set_quiet_hours(database: &SqliteDatabase, guild_id: GuildId, user_id: UserId,
+                         quiet_hours: &QuietHours, role_id: Option<RoleId>) -> Result<String, sqlx::Error> {
+    let setting = database.get_guild_setting(guild_id).await?;
+    clear_quiet_reservation(database, &setting, guild_id).await?;
+
+    let tz = setting.tz();
+    let target = role_id.map_or(Target::Server, Target::Role);
+    let repeat = Repeat { recurrence: Recurrence::Daily, local_time: quiet_hours.start, tz };
+    let first = repeat.first_on_or_after(Utc::now().naive_utc());
+    let options = ReservationOptions {
+        schedule: Some(Schedule::Repeat(repeat)),
+        policy: Some(Policy::QuietHours),
+        ..Default::default()
+    };
+
+    let reservation_id = database.insert_reservation(user_id, guild_id, &target, &options).await?;
+    database.insert_disconnect_jobs(reservation_id, first, user_id, guild_id).await?;
+    database.update_guild_quiet_hours(guild_id, Some(quiet_hours), role_id, Some(reservation_id)).await?;
+
+    Ok(format!("静粛時間を「{}」に設定しました\n対象: {}\nタイムゾーン: {}",
+               quiet_hours.describe(), target.mention(), describe_timezone(tz)))
+}
+
+// 毎日の予約は作成時のタイムゾーンで保存しているので、ギルドのタイムゾーンの変更後に作り直す
+pub async fn recreate_quiet_hours(database: &SqliteDatabase, guild_id: GuildId, user_id: UserId)
+                                  -> Result<(), sqlx::Error> {
+    let setting = database.get_guild_setting(guild_id).await?;
+    let Some(quiet_hours) = setting.quiet_hours() else {
+        return Ok(());
+    };
+    // 予約者は変えない
+    let owner_id = match setting.quiet_reservation_id {
+        Some(reservation_id) => database.get_reservation(reservation_id).await?.map(|r| r.userid()),
+        None => None
+    };
+    set_quiet_hours(database, guild_id, owner_id.unwrap_or(user_id), &quiet_hours, setting.quiet_role()).await?;
+    Ok(())
+}
+
+async fn clear_quiet_hours(database: &SqliteDatabase, guild_id: GuildId) -> Result<String, sqlx::Error> {
+    let setting = database.get_guild_setting(guild_id).await?;
+    clear_quiet_reservation(database, &setting, guild_id).await?;
+    database.update_guild_quiet_hours(guild_id, None, None, None).await?;
+
+    Ok("静粛時間を解除しました".to_string())
+}
+
+async fn get_quiet_hours(database: &SqliteDatabase, guild_id: GuildId) -> Result<String, sqlx::Error> {
+    let setting = database.get_guild_setting(guild_id).await?;
+
+    Ok(match setting.quiet_hours() {
+        Some(quiet_hours) => {
+            let target = setting.quiet_role().map_or(Target::Server, Target::Role);
+            format!("静粛時間は「{}」に設定されています\n対象: {}\nタイムゾーン: {}",
+                    quiet_hours.describe(), target.mention(), describe_timezone(setting.tz()))
+        },
+        None => "静粛時間は設定されていません".to_string()
+    })
+}
+
+async fn quiet_hours(database: Arc<SqliteDatabase>, guild_id: GuildId, user_id: UserId,
+                     options: &[CommandDataOption]) -> String {
+    let start = string_option_ref(options, "start");
+    let end = string_option_ref(options, "end");
+    let role_id = role_option_ref(options, "role").map(|r| r.id);
+
+    let result = if bool_option_ref(options, "off") == Some(&true) {
+        clear_quiet_hours(&database, guild_id).await
+    } else if let (Some(start), Some(end)) = (start, end) {
+        let quiet_hours = match (parse_local_time(start), parse_local_time(end)) {
+            (Some(start), Some(end)) => QuietHours::new(start, end),
+            _ => Err("静粛時間の時刻を認識できません".to_string())
+        };
+        match quiet_hours {
+            Ok(quiet_hours) => set_quiet_hours(&database, guild_id, user_id, &quiet_hours, role_id).await,
+            Err(why) => return why
+        }
+    } else if start.is_some() || end.is_some() {
+        return "静粛時間の開始と終了を両方指定してください".to_string();
+    } else {
+        get_quiet_hours(&database, guild_id).await
+    };
+
+    result.unwrap_or_else(|why| {
+        println!("{:?}", why);
+        "静粛時間の設定に失敗しました".to_string()
+    })
+}
+
+// 通話中のユーザーの切断予約も新しい上限で作り直す
+async fn set_max_session(database: &SqliteDatabase, guild_id: GuildId, minutes: Option<i64>)
+                         -> Result<String, sqlx::Error> {
+    database.update_guild_max_session(guild_id, minutes).await?;
+    reschedule_sessions(database, guild_id).await?;
+
+    Ok(match minutes {
+        Some(m) => format!("連続通話の上限を「{}」に設定しました", describe_duration(Duration::minutes(m))),
+        None => "連続通話の上限を解除しました".to_string()
+    })
+}
+
+async fn get_max_session(database: &SqliteDatabase, guild_id: GuildId) -> Result<String, sqlx::Error> {
+    let setting = database.get_guild_setting(guild_id).await?;
+
+    Ok(match setting.max_session() {
+        Some(d) => format!("連続通話の上限は「{}」に設定されています", describe_duration(d)),
+        None => "連続通話の上限は設定されていません".to_string()
+    })
+}
+
+async fn max_session(database: Arc<SqliteDatabase>, guild_id: GuildId,
+                     options: &[CommandDataOption]) -> String {
+    let minutes = int_option_ref(options, "minutes").copied();
+
+    let result = if bool_option_ref(options, "off") == Some(&true) {
+        set_max_session(&database, guild_id, None).await
+    } else if minutes.is_some() {
+        set_max_session(&database, guild_id, minutes).await
+    } else {
+        get_max_session(&database, guild_id).await
+    };
+
+    result.unwrap_or_else(|why| {
+        println!("{:?}", why);
+        "連続通話の上限の設定に失敗しました".to_string()
+    })
+}
+
+async fn action(ctx: &Context, database: Arc<SqliteDatabase>, guild_id: GuildId,
+                options: &[CommandDataOption]) -> String {
+    // AFKチャンネル未設定のギルドでは実際に行う動作（切断）を表示する
+    let describe = |action: Action| match ctx.cache.guild(guild_id) {
+        Some(guild) if action == Action::Afk && guild.afk_channel_id.is_none() =>
+            format!("{} (AFKチャンネルが未設定のため{})", action.describe(), action.describe_in(&guild)),
+        _ => action.describe()
+    };
+    let result = match action_option(options) {
+        Err(why) => return why,
+        Ok(Some(action)) => database.update_guild_action(guild_id, action).await
+            .map(|_| format!("切断時の動作を「{}」に設定しました", describe(action))),
+        Ok(None) => database.get_guild_setting(guild_id).await
+            .map(|setting| format!("切断時の動作は「{}」に設定されています", describe(setting.action())))
+    };
+
+    result.unwrap_or_else(|why| {
+        println!("{:?}", why);
+        "切断時の動作の設定に失敗しました".to_string()
+    })
+}
+
+async fn horizon(database: Arc<SqliteDatabase>, guild_id: GuildId, options: &[CommandDataOption]) -> String {
+    let result = match int_option_ref(options, "days").copied() {
+        Some(days) => database.update_guild_max_horizon(guild_id, days).await
+            .map(|_| format!("相対指定で予約できる時間を「{}日先まで」に設定しました", days)),
+        None => database.get_guild_setting(guild_id).await
+            .map(|setting| format!("相対指定で予約できる時間は「{}日先まで」に設定されています",
+                                   setting.max_horizon_days))
+    };
+
+    result.unwrap_or_else(|why| {
+        println!("{:?}", why);
+        "予約できる時間の上限の設定に失敗しました".to_string()
+    })
+}
+
+pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.unwrap();
+    let database = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<SqliteDatabase>().unwrap().clone()
+    };
+
+    let subcommand = command.data.options.first().unwrap();
+    let options = &subcommand.options;
+
+    let content = match subcommand.name.as_str() {
+        "warnings" => {
+            if let Some(times) = string_option_ref(options, "times") {
+                set_warnings(database, guild_id, times).await
+            } else {
+                get_warnings(database, guild_id).await
+            }
+        },
+        "snooze" => {
+            let count = int_option_ref(options, "count").copied();
+            let minutes = int_option_ref(options, "minutes").copied();
+            if count.is_some() || minutes.is_some() {
+                set_snooze(database, guild_id, count, minutes).await
+            } else {
+                get_snooze(database, guild_id).await
+            }
+        },
+        "quiet_hours" => quiet_hours(database, guild_id, command.user.id, options).await,
+        "session" => max_session(database, guild_id, options).await,
+        "action" => action(&ctx, database, guild_id, options).await,
+        "horizon" => horizon(database, guild_id, options).await,
+        _ => panic!("unexpected subcommand.")
+    };
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message|
+                    message.content(content)
+                )
+        })
+        .await
+        .unwrap_or_else(|why| println!("cannot respond to slash command: {}", why));
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command.name("config").description("ギルドの設定を変更/表示します")
+        .default_member_permissions(Permissions::MANAGE_GUILD)
+        .create_option(|subcommand| {
+            subcommand
+                .name("warnings")
+                .description("切断前通知のタイミングを設定/表示します")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("times")
+                        .description("切断の何分前に通知するか (例: 30m,10m,1m / なし)")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+        })
+        .create_option(|subcommand| {
+            subcommand
+                .name("snooze")
+                .description("切断前通知からの延長の上限を設定/表示します")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("count")
+                        .description("1回の切断予約で延長できる回数 (0で延長不可)")
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(0)
+                        .max_int_value(10)
+                        .required(false)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("minutes")
+                        .description("1回の切断予約で延長できる合計時間 (分)")
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(0)
+                        .max_int_value(240)
+                        .required(false)
+                })
+        })
+        .create_option(|subcommand| {
+            subcommand
+                .name("quiet_hours")
+                .description("通話に参加できない時間帯を設定/表示します")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("start")
+                        .description("開始時刻 (例: 2:00)")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("end")
+                        .description("終了時刻 (例: 6:00)")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("role")
+                        .description("対象のRole (未指定なら全員)")
+                        .kind(CommandOptionType::Role)
+                        .required(false)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("off")
+                        .description("静粛時間を解除します")
+                        .kind(CommandOptionType::Boolean)
+                        .required(false)
+                })
+        })
+        .create_option(|subcommand| {
+            subcommand
+                .name("session")
+                .description("連続して通話できる時間の上限を設定/表示します")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("minutes")
+                        .description("上限 (分, 例: 240)")
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(10)
+                        .max_int_value(24 * 60)
+                        .required(false)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("off")
+                        .description("上限を解除します")
+                        .kind(CommandOptionType::Boolean)
+                        .required(false)
+                })
+        })
+        .create_option(|subcommand| {
+            subcommand
+                .name("action")
+                .description("切断時の動作を設定/表示します")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("action")
+                        .description("切断時の動作")
+                        .kind(CommandOptionType::String)
+                        .add_string_choice("切断", "disconnect")
+                        .add_string_choice("指定チャンネルへ移動", "move")
+                        .add_string_choice("AFKチャンネルへ移動", "afk")
+                        .required(false)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("move_to")
+                        .description("移動先のボイスチャンネル")
+                        .kind(CommandOptionType::Channel)
+                        .channel_types(&[ChannelType::Voice, ChannelType::Stage])
+                        .required(false)
+                })
+        })
+        .create_option(|subcommand| {
+            subcommand
+                .name("horizon")
+                .description("相対指定 (例: 2d) で何日先まで予約できるかを設定/表示します")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("days")
+                        .description("上限 (日)")
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(1)
+                        .max_int_value(365)
+                        .required(false)
+                })
+        })
+}
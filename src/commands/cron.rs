@@ -0,0 +1,112 @@
+use serenity::{
+    builder::CreateApplicationCommand,
+    model::{
+        channel::ChannelType,
+        application::{
+            command::CommandOptionType,
+            interaction::{
+                application_command::ApplicationCommandInteraction,
+                InteractionResponseType
+            },
+        },
+    },
+    prelude::*
+};
+use std::sync::Arc;
+use crate::SqliteDatabase;
+use crate::job::ReservationOptions;
+use crate::schedule::{CronSchedule, Schedule};
+use crate::commands::snipe::add_job;
+use crate::commands::utils::{string_option_ref, collect_targets, describe_timezone};
+
+// 返信に表示する発生時刻の数
+const PREVIEW_COUNT: usize = 5;
+
+pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.unwrap();
+    let user_id = command.user.id;
+    let database = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<SqliteDatabase>().unwrap().clone()
+    };
+
+    let expression = string_option_ref(&command.data.options, "expression").unwrap();
+    let tz = database.get_guild_setting(guild_id).await.unwrap().tz();
+
+    let targets = collect_targets(&ctx, &command.data.options, guild_id, user_id).await;
+
+    let content = match (CronSchedule::parse(expression, tz), targets) {
+        (Err(why), _) | (_, Err(why)) => why,
+        (Ok(cron), Ok(targets)) => {
+            let upcoming = cron.upcoming(PREVIEW_COUNT);
+            let schedule = Schedule::Cron(Box::new(cron));
+            let description = schedule.describe();
+            let options = ReservationOptions { schedule: Some(schedule), ..Default::default() };
+
+            for target in targets.iter() {
+                add_job(database.clone(), upcoming[0], user_id, guild_id, target, &options).await;
+            }
+
+            let target_users_str = targets.iter().map(|t| t.mention()).collect::<Vec<_>>().join(" ");
+            let upcoming_str: String = upcoming.iter()
+                .map(|t| format!("\n- <t:{0}:F> (<t:{0}:R>)", t.and_utc().timestamp()))
+                .collect();
+
+            format!("{}を {} で切断します\nタイムゾーン: {}\n次回以降の予定:{}",
+                    target_users_str, description, describe_timezone(tz), upcoming_str)
+        }
+    };
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message|
+                    message.content(content)
+                )
+        })
+        .await
+        .unwrap_or_else(|why| println!("cannot respond to slash command: {}", why));
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command.name("cron").description("cron式でギルドのタイムゾーンの繰り返し切断予約をします")
+        .create_option(|option| {
+            option
+                .name("expression")
+                .description("分 時 日 月 曜日 (例: 0 23 * * 1-5, 毎月最終金曜22時: 0 22 * * 5#L)")
+                .kind(CommandOptionType::String)
+                .required(true)
+        })
+        .create_option(|option| {
+            option
+                .name("role")
+                .description("Roleに対して切断予約します")
+                .kind(CommandOptionType::Role)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("user")
+                .description("ユーザーに対して切断予約します")
+                .kind(CommandOptionType::User)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("channel")
+                .description("ボイスチャンネルの全員を切断予約します")
+                .kind(CommandOptionType::Channel)
+                .channel_types(&[ChannelType::Voice, ChannelType::Stage])
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("scope")
+                .description("参加中のボイスチャンネル/サーバー全体の全員を切断予約します")
+                .kind(CommandOptionType::String)
+                .add_string_choice("参加中のボイスチャンネル", "vc")
+                .add_string_choice("サーバー全体", "server")
+                .required(false)
+        })
+}
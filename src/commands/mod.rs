@@ -1,5 +1,13 @@
 pub mod utils;
 pub mod show;
 pub mod clear;
+pub mod cancel;
+pub mod reschedule;
 pub mod snipe;
+pub mod cron;
 pub mod timezone;
+pub mod status;
+pub mod config;
+pub mod budget;
+pub mod remind;
+pub mod schedule;
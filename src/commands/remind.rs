@@ -0,0 +1,257 @@
+use serenity::{
+    builder::CreateApplicationCommand,
+    model::{
+        channel::ChannelType,
+        id::{GuildId, UserId},
+        application::{
+            command::CommandOptionType,
+            interaction::{
+                application_command::ApplicationCommandInteraction,
+                InteractionResponseType
+            },
+        },
+    },
+    prelude::*
+};
+use std::{sync::Arc, collections::HashSet};
+use chrono::{Utc, NaiveDateTime};
+use crate::SqliteDatabase;
+use crate::job::{Target, ReservationOptions};
+use crate::actions::reminder::{Reminder, ReminderParams};
+use crate::schedule::{Recurrence, Repeat, Schedule};
+use crate::commands::snipe::reservation_time;
+use crate::commands::utils::*;
+
+// メッセージの最大文字数
+const MAX_MESSAGE_LEN: usize = 200;
+
+pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.unwrap();
+    let user_id = command.user.id;
+    let database = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<SqliteDatabase>().unwrap().clone()
+    };
+
+    let content = match reserve(&ctx, &database, command, guild_id, user_id).await {
+        Ok(content) | Err(content) => content
+    };
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message|
+                    message.content(content)
+                )
+        })
+        .await
+        .unwrap_or_else(|why| println!("cannot respond to slash command: {}", why));
+}
+
+// リマインダーを登録し、返信する内容を返す
+async fn reserve(ctx: &Context, database: &Arc<SqliteDatabase>, command: &ApplicationCommandInteraction,
+                 guild_id: GuildId, user_id: UserId) -> Result<String, String> {
+    let options = &command.data.options;
+
+    let time = string_option_ref(options, "time").unwrap();
+
+    let recurrence = string_option_ref(options, "repeat").map(|r| Recurrence::parse(r)).transpose()?;
+    let message = string_option_ref(options, "message").map(|m| m.trim().to_string());
+    if message.as_ref().is_some_and(|m| m.chars().count() > MAX_MESSAGE_LEN) {
+        return Err(format!("メッセージは{}文字以内にしてください", MAX_MESSAGE_LEN));
+    }
+    let params = ReminderParams {
+        always: bool_option_ref(options, "always").copied().unwrap_or(false),
+        message: message.filter(|m| !m.is_empty()),
+    };
+
+    // 時間指定は既定で時刻、繰り返しは時刻指定のみ
+    let relative = string_option_ref(options, "type").is_some_and(|t| t == "in");
+    if relative && recurrence.is_some() {
+        return Err("繰り返し予約は時刻指定 (type:at) のみ対応しています".to_string());
+    }
+
+    let targets = collect_targets(ctx, options, guild_id, user_id).await?;
+    if params.always {
+        let manage_guild = command.member.as_ref()
+            .and_then(|member| member.permissions)
+            .is_some_and(|permissions| permissions.manage_guild());
+        check_always(manage_guild, &targets)?;
+    }
+
+    let tz = database.get_timezone(user_id, guild_id).await
+        .map_err(|_| "タイムゾーンの取得に失敗しました".to_string())?;
+    let horizon = database.get_guild_setting(guild_id).await
+        .map_err(|_| "サーバーの設定の取得に失敗しました".to_string())?
+        .max_horizon();
+    let (target_datetime, repeat) = if relative {
+        (reservation_time(time, "in", tz, horizon)?, None)
+    } else {
+        let target_datetime = reservation_time(time, "at", tz, horizon)?;
+        match recurrence {
+            Some(recurrence) => {
+                let local_time = target_datetime.and_utc().with_timezone(&tz).time();
+                let repeat = Repeat { recurrence, local_time, tz };
+                (repeat.first_on_or_after(target_datetime), Some(repeat))
+            },
+            None => (target_datetime, None)
+        }
+    };
+    if target_datetime <= Utc::now().naive_utc() {
+        return Err("過去の時刻には予約できません".to_string());
+    }
+
+    let reservation = ReservationOptions {
+        schedule: repeat.clone().map(Schedule::Repeat),
+        ..Default::default()
+    };
+    for target in targets.iter() {
+        add_reminder(database, target_datetime, user_id, guild_id, target, &reservation, &params).await;
+    }
+
+    let target_users_str = targets.iter().map(|t| t.mention()).collect::<Vec<_>>().join(" ");
+    let mut content = format!("{0}に<t:{1}:T> (<t:{1}:R>)にリマインドします\nメッセージ: {2}",
+                              target_users_str, target_datetime.and_utc().timestamp(), params.message());
+    if let Some(repeat) = repeat.as_ref() {
+        content += &format!("\n繰り返し: {} {}", repeat.recurrence.describe(), repeat.local_time.format("%H:%M"));
+    }
+    if !relative {
+        content += &format!("\nタイムゾーン: {}", describe_timezone(tz));
+    }
+    if params.always {
+        content += "\n通話に参加していなくても通知します";
+    }
+    Ok(content)
+}
+
+// 通話外への通知は、ユーザー指定なら誰でも、ロールへはサーバー管理の権限があるときだけ
+// （チャンネル/サーバー全体は通話中のユーザーにしか展開できないので指定できない）
+fn check_always(manage_guild: bool, targets: &HashSet<Target>) -> Result<(), String> {
+    for target in targets.iter() {
+        match target {
+            Target::User(_) => {},
+            Target::Role(_) if manage_guild => {},
+            Target::Role(_) =>
+                return Err("ロールへの通話外の通知 (always) にはサーバー管理の権限が必要です".to_string()),
+            Target::Channel(_) | Target::Server =>
+                return Err("always はボイスチャンネル/サーバー全体には指定できません（通話中のユーザーにのみ通知します）".to_string()),
+        }
+    }
+    Ok(())
+}
+
+// snipe::add_jobと同じく、ユーザー指定の予約はそのユーザーのものとして登録
+async fn add_reminder(database: &SqliteDatabase, naive_utc: NaiveDateTime,
+                      user_id: UserId, guild_id: GuildId, target: &Target,
+                      options: &ReservationOptions, params: &ReminderParams) {
+    let user_id = match target {
+        Target::User(u) => *u,
+        _ => user_id
+    };
+
+    let reservation_id = match database.insert_reservation(user_id, guild_id, target, options).await {
+        Ok(id) => id,
+        Err(why) => {
+            println!("{:?}", why);
+            return;
+        }
+    };
+
+    if let Err(why) = database
+        .insert_action_job(reservation_id, naive_utc, user_id, guild_id,
+                           Reminder::NAME, Some(&params.to_params()))
+        .await
+    {
+        println!("{:?}", why);
+    }
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command.name("remind").description("切断せずにDMで知らせるだけの予約をします")
+        .create_option(|option| {
+            option
+                .name("time")
+                .description("知らせる時刻/知らせるまでの時間")
+                .kind(CommandOptionType::String)
+                .required(true)
+        })
+        .create_option(|option| {
+            option
+                .name("message")
+                .description("通知するメッセージ")
+                .kind(CommandOptionType::String)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("type")
+                .description("指定方法を選択します (at: 時刻, in: 時間後。既定はat)")
+                .kind(CommandOptionType::String)
+                .add_string_choice("at", "at")
+                .add_string_choice("in", "in")
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("repeat")
+                .description("繰り返し (例: daily, weekdays, mon,wed,fri, 月水金, 3日ごと)")
+                .kind(CommandOptionType::String)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("always")
+                .description("通話に参加していなくても通知します (ユーザー/ロールのみ。ロールにはサーバー管理の権限が必要)")
+                .kind(CommandOptionType::Boolean)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("role")
+                .description("Roleに対してリマインドします")
+                .kind(CommandOptionType::Role)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("user")
+                .description("ユーザーに対してリマインドします")
+                .kind(CommandOptionType::User)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("channel")
+                .description("ボイスチャンネルの全員にリマインドします")
+                .kind(CommandOptionType::Channel)
+                .channel_types(&[ChannelType::Voice, ChannelType::Stage])
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("scope")
+                .description("参加中のボイスチャンネル/サーバー全体の全員にリマインドします")
+                .kind(CommandOptionType::String)
+                .add_string_choice("参加中のボイスチャンネル", "vc")
+                .add_string_choice("サーバー全体", "server")
+                .required(false)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::model::id::{ChannelId, RoleId};
+
+    #[test]
+    fn always_targets() {
+        let targets = |target| HashSet::from([target]);
+        assert!(check_always(false, &targets(Target::User(UserId(1)))).is_ok());
+        assert!(check_always(false, &targets(Target::Role(RoleId(2)))).is_err());
+        assert!(check_always(true, &targets(Target::Role(RoleId(2)))).is_ok());
+        // 通話中のユーザーにしか展開できない対象は権限があっても指定できない
+        assert!(check_always(true, &targets(Target::Channel(ChannelId(3)))).is_err());
+        assert!(check_always(true, &targets(Target::Server)).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use serenity::{
+    builder::CreateApplicationCommand,
+    model::{
+        application::{
+            command::CommandOptionType,
+            interaction::{
+                application_command::ApplicationCommandInteraction,
+                InteractionResponseType
+            },
+        },
+    },
+    prelude::*
+};
+use std::sync::Arc;
+use chrono::{Utc, NaiveDateTime};
+use chrono_tz::Tz;
+use crate::{SqliteDatabase, voice};
+use crate::actions::reminder::Reminder;
+use crate::commands::utils::{int_option_ref, string_option_ref, describe_timezone};
+use crate::time_expr::{TimeExpr, parse_duration};
+
+// 時刻指定のときはタイムゾーンも表示する
+fn rescheduled_content(kind: &str, reservation_id: i64, target_datetime: NaiveDateTime, tz: Option<Tz>) -> String {
+    let mut content = format!("{0} #{1} を<t:{2}:T> (<t:{2}:R>)に変更しました",
+                              kind, reservation_id, target_datetime.and_utc().timestamp());
+    if let Some(tz) = tz {
+        content += &format!("\nタイムゾーン: {}", describe_timezone(tz));
+    }
+    content
+}
+
+pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.unwrap();
+    let user_id = command.user.id;
+    let database = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<SqliteDatabase>().unwrap().clone()
+    };
+
+    let reservation_id = *int_option_ref(&command.data.options, "id").unwrap();
+    let time = string_option_ref(&command.data.options, "time").unwrap().trim();
+
+    let reservation = database.get_user_reservations(user_id, guild_id).await
+        .unwrap()
+        .into_iter()
+        .find(|job| job.reservation_id == reservation_id);
+
+    let content = match reservation {
+        None => format!("切断予約 #{} が見つかりません", reservation_id),
+        Some(job) => {
+            // 先頭の+/-は現在の予約時刻からの相対指定
+            let (sign, time_body) = match time.chars().next() {
+                Some(c @ ('+' | '-' | '＋' | '－')) => (Some(c), &time[c.len_utf8()..]),
+                _ => (None, time)
+            };
+
+            let tz = database.get_timezone(user_id, guild_id).await.unwrap();
+            let setting = database.get_guild_setting(guild_id).await.unwrap();
+            // 段階的な切断では最初の段階（ミュート）を基準にする
+            let deadline = database.get_deadline(reservation_id).await
+                .unwrap()
+                .unwrap_or(job.naive_utc);
+            let target_datetime = match sign {
+                Some(c) => parse_duration(time_body, setting.max_horizon())
+                    .map(|duration| if matches!(c, '+' | '＋') { deadline + duration } else { deadline - duration })
+                    .map_err(|why| why.help()),
+                None => TimeExpr::parse(time)
+                    .and_then(|expr| expr.resolve(tz, Utc::now()))
+                    .map_err(|why| why.help())
+            };
+
+            match target_datetime {
+                Err(why) => why,
+                Ok(target_datetime) if target_datetime <= Utc::now().naive_utc() => {
+                    "過去の時刻には変更できません".to_string()
+                },
+                Ok(target_datetime) if job.is(Reminder::NAME) => {
+                    database.reschedule_reminder_job(reservation_id, target_datetime).await.unwrap();
+                    rescheduled_content("リマインダー", reservation_id, target_datetime, sign.is_none().then_some(tz))
+                },
+                Ok(target_datetime) => {
+                    database.reschedule_disconnect_jobs(reservation_id, target_datetime,
+                                                        job.userid(), guild_id)
+                        .await
+                        .unwrap();
+                    // 期限が延びたので既に適用したミュートを解除する
+                    database.release_reservation_mutes(reservation_id).await.unwrap();
+                    voice::release_mutes(&ctx, &database, guild_id).await;
+
+                    rescheduled_content("切断予約", reservation_id, target_datetime, sign.is_none().then_some(tz))
+                }
+            }
+        }
+    };
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message|
+                    message.content(content)
+                )
+        })
+        .await
+        .unwrap_or_else(|why| println!("cannot respond to slash command: {}", why));
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command.name("reschedule").description("通話の切断予約の時刻を変更します")
+        .create_option(|option| {
+            option
+                .name("id")
+                .description("変更する予約")
+                .kind(CommandOptionType::Integer)
+                .set_autocomplete(true)
+                .required(true)
+        })
+        .create_option(|option| {
+            option
+                .name("time")
+                .description("新しい時刻 (例: 23:30) / ずらす時間 (例: +15m, -1h)")
+                .kind(CommandOptionType::String)
+                .required(true)
+        })
+}
@@ -0,0 +1,355 @@
+use serenity::{
+    builder::{CreateApplicationCommand, CreateInputText},
+    model::{
+        guild::Guild,
+        channel::{Channel, ChannelType},
+        id::{ChannelId, GuildId, RoleId, UserId},
+        application::{
+            command::CommandType,
+            component::{ActionRowComponent, InputTextStyle},
+            interaction::{
+                application_command::ApplicationCommandInteraction,
+                modal::ModalSubmitInteraction,
+                InteractionResponseType
+            },
+        },
+    },
+    prelude::*
+};
+use std::{sync::Arc, collections::HashMap};
+use crate::SqliteDatabase;
+use crate::job::{Target, Action, SnipeRequest};
+use crate::schedule::LeadTimes;
+use crate::commands::snipe::{Plan, split_choice};
+use crate::time_expr::{TimeExpr, parse_duration};
+use crate::commands::utils::SnipeType;
+
+// ユーザーのコンテキストメニューに表示する名前
+pub const USER_MENU: &str = "Schedule…";
+
+// 理由の最大文字数
+const MAX_REASON_LEN: usize = 200;
+
+fn input_text(custom_id: &str, label: &str, placeholder: &str, style: InputTextStyle,
+              max_length: u64, required: bool) -> CreateInputText {
+    let mut input = CreateInputText::default();
+    input.custom_id(custom_id);
+    input.label(label);
+    input.placeholder(placeholder);
+    input.style(style);
+    input.max_length(max_length);
+    input.required(required);
+    input
+}
+
+// /schedule と ユーザーのメニューから予約のモーダルを開く（メニューからは対象を入力済みにする）
+pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
+    let target = command.data.target_id.map(|id| format!("<@{}>", id.0));
+
+    let mut target_input = input_text("target", "切断対象 (空欄なら自分)", "@ユーザー @ロール #ボイスチャンネル vc server",
+                                      InputTextStyle::Short, 200, false);
+    if let Some(target) = target {
+        target_input.value(target);
+    }
+    let inputs = [
+        target_input,
+        input_text("time", "時刻/時間", "23:30, 明日 1時, 1h30m (at:/in: で指定方法を固定)",
+                   InputTextStyle::Short, 100, true),
+        input_text("warn", "事前通知 (空欄ならサーバーの設定)", "30m,10m,1m",
+                   InputTextStyle::Short, 100, false),
+        input_text("action", "切断時の動作 (空欄ならサーバーの設定)", "disconnect, afk, #移動先のボイスチャンネル",
+                   InputTextStyle::Short, 100, false),
+        input_text("reason", "理由", "確認と/showに表示します",
+                   InputTextStyle::Paragraph, MAX_REASON_LEN as u64, false),
+    ];
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::Modal)
+                .interaction_response_data(|modal| {
+                    modal
+                        .custom_id("schedule")
+                        .title("切断予約")
+                        .components(|c| {
+                            for input in inputs {
+                                c.create_action_row(|row| row.add_input_text(input));
+                            }
+                            c
+                        })
+                })
+        })
+        .await
+        .unwrap_or_else(|why| println!("cannot open modal: {}", why));
+}
+
+// モーダルの入力を/snipeと同じ方法で解釈し、確定前の確認を表示する
+pub async fn submit(ctx: Arc<Context>, modal: &ModalSubmitInteraction) {
+    let guild_id = modal.guild_id.unwrap();
+    let user_id = modal.user.id;
+    let database = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<SqliteDatabase>().unwrap().clone()
+    };
+
+    let values: HashMap<&str, &str> = modal.data.components
+        .iter()
+        .flat_map(|row| row.components.iter())
+        .filter_map(|component| match component {
+            ActionRowComponent::InputText(input) => Some((input.custom_id.as_str(), input.value.trim())),
+            _ => None
+        })
+        .collect();
+
+    let content = match preview(&ctx, &database, &values, guild_id, user_id).await {
+        Ok((content, draft_id, types)) => {
+            modal
+                .create_interaction_response(&ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|message| {
+                            message
+                                .content(content)
+                                .components(|c| c.add_action_row(SnipeType::confirm_row(draft_id, &types)))
+                        })
+                })
+                .await
+                .unwrap_or_else(|why| println!("cannot respond to modal: {}", why));
+            return;
+        },
+        Err(why) => why
+    };
+
+    modal
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .ephemeral(true)
+                        .content(content)
+                })
+        })
+        .await
+        .unwrap_or_else(|why| println!("cannot respond to modal: {}", why));
+}
+
+// 入力を保存し、確認の内容と保存した入力のid、予約できる指定方法を返す
+async fn preview(ctx: &Context, database: &Arc<SqliteDatabase>, values: &HashMap<&str, &str>,
+                 guild_id: GuildId, user_id: UserId) -> Result<(String, i64, Vec<SnipeType>), String> {
+    let value = |name: &str| values.get(name).copied().filter(|v| !v.is_empty());
+    let guild = ctx.cache.guild(guild_id).ok_or_else(|| "サーバーの情報を取得できません".to_string())?;
+
+    let reason = value("reason").map(|r| r.to_string());
+    if reason.as_ref().is_some_and(|r| r.chars().count() > MAX_REASON_LEN) {
+        return Err(format!("理由は{}文字以内にしてください", MAX_REASON_LEN));
+    }
+    let (chosen, time) = split_choice(value("time").unwrap_or_default());
+    let request = SnipeRequest {
+        time: time.to_string(),
+        recurrence: None,
+        lead_times: value("warn").map(LeadTimes::parse).transpose()?,
+        lockout: None,
+        action: value("action").map(|a| parse_action(&guild, a)).transpose()?,
+        escalate: false,
+        reason,
+        targets: parse_targets(&guild, value("target").unwrap_or_default(), user_id)?,
+    };
+
+    // 指定方法が入力から決まらなければ、解釈できる方法をすべて候補にする
+    let candidates = match chosen {
+        Some("in") => vec![SnipeType::Relative],
+        Some(_) => vec![SnipeType::Absolute],
+        None => vec![SnipeType::Absolute, SnipeType::Relative]
+    };
+    let mut plans = Vec::new();
+    let mut errors = Vec::new();
+    for snipe_type in candidates {
+        match Plan::new(database, &request, guild_id, user_id, snipe_type.custom_id()).await {
+            Ok(plan) => plans.push((snipe_type, plan)),
+            Err(why) => errors.push((snipe_type, why))
+        }
+    }
+    if plans.is_empty() {
+        // 入力を読めた方の指定方法のエラーを返す（どちらとしても読めなければ時刻のエラー）
+        let horizon = database.get_guild_setting(guild_id).await
+            .map_err(|_| "サーバーの設定の取得に失敗しました".to_string())?
+            .max_horizon();
+        let understood = |snipe_type: SnipeType| match snipe_type {
+            SnipeType::Absolute => TimeExpr::parse(time).is_ok(),
+            SnipeType::Relative => parse_duration(time, horizon).map_or_else(|why| !why.is_syntax(), |_| true),
+        };
+        let index = errors.iter().position(|&(snipe_type, _)| understood(snipe_type)).unwrap_or(0);
+        return Err(errors.swap_remove(index).1);
+    }
+
+    let content = match plans.as_slice() {
+        [(_, plan)] => format!("以下の内容で予約しますか？\n{}", plan.describe(&request.targets, &guild)),
+        _ => plans.iter()
+            .map(|(snipe_type, plan)| format!("{} {}として:\n{}", snipe_type.emoji(), snipe_type,
+                                              plan.describe(&request.targets, &guild)))
+            .fold("時刻/時間後のどちらで予約しますか？".to_string(), |content, p| content + "\n\n" + &p)
+    };
+    let draft_id = database.insert_snipe_draft(user_id, guild_id, &request).await
+        .map_err(|why| {
+            println!("{:?}", why);
+            "予約の受付に失敗しました".to_string()
+        })?;
+    Ok((content, draft_id, plans.into_iter().map(|(snipe_type, _)| snipe_type).collect()))
+}
+
+// <@id> / <@&id> / <#id> のメンション
+fn mention_id(token: &str, prefix: &str) -> Option<u64> {
+    token.strip_prefix(prefix)?.strip_suffix('>')?.parse().ok()
+}
+
+// /snipeのchannelオプションと同じく、このサーバーのボイス/ステージチャンネルだけを受け付ける
+fn is_voice_channel(channel: &Channel) -> bool {
+    matches!(channel, Channel::Guild(c) if matches!(c.kind, ChannelType::Voice | ChannelType::Stage))
+}
+
+fn voice_channel(guild: &Guild, id: u64) -> Option<ChannelId> {
+    guild.channels.get(&ChannelId(id)).filter(|c| is_voice_channel(c)).map(|c| c.id())
+}
+
+fn voice_channel_named(guild: &Guild, name: &str) -> Option<ChannelId> {
+    guild.channels.values().find_map(|channel| match channel {
+        Channel::Guild(c) if c.name == name && is_voice_channel(channel) => Some(c.id),
+        _ => None
+    })
+}
+
+// メンションのほか、モーダルではメンションを補完できないので @名前 / #名前 でも指定できる
+fn parse_targets(guild: &Guild, input: &str, user_id: UserId) -> Result<Vec<Target>, String> {
+    let mut targets = Vec::new();
+    for token in input.split(|c: char| c.is_whitespace() || c == ',' || c == '、').filter(|t| !t.is_empty()) {
+        let target = if let Some(r) = mention_id(token, "<@&") {
+            Target::Role(RoleId(r))
+        } else if let Some(u) = mention_id(token, "<@!").or_else(|| mention_id(token, "<@")) {
+            Target::User(UserId(u))
+        } else if let Some(c) = mention_id(token, "<#") {
+            Target::Channel(voice_channel(guild, c)
+                .ok_or_else(|| format!("{}はこのサーバーのボイスチャンネルではありません", token))?)
+        } else if let Some(name) = token.strip_prefix('#') {
+            Target::Channel(voice_channel_named(guild, name)
+                .ok_or_else(|| format!("ボイスチャンネル「{}」が見つかりません", name))?)
+        } else if let Some(name) = token.strip_prefix('@') {
+            let role = guild.roles.values().find(|r| r.name == name).map(|r| Target::Role(r.id));
+            let member = guild.members.values()
+                .find(|m| m.user.name == name || m.nick.as_deref() == Some(name))
+                .map(|m| Target::User(m.user.id));
+            role.or(member).ok_or_else(|| format!("ロール/ユーザー「{}」が見つかりません", name))?
+        } else {
+            match token.to_lowercase().as_str() {
+                "me" | "自分" => Target::User(user_id),
+                "server" | "サーバー" => Target::Server,
+                "vc" => Target::Channel(guild.voice_states.get(&user_id)
+                    .and_then(|v| v.channel_id)
+                    .ok_or_else(|| "ボイスチャンネルに参加していません".to_string())?),
+                _ => return Err(format!("「{}」を切断対象として認識できません", token))
+            }
+        };
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    if targets.is_empty() {
+        targets.push(Target::User(user_id));
+    }
+    Ok(targets)
+}
+
+fn parse_action(guild: &Guild, input: &str) -> Result<Action, String> {
+    let channel = match mention_id(input, "<#") {
+        Some(c) => Some(voice_channel(guild, c)
+            .ok_or_else(|| format!("{}はこのサーバーのボイスチャンネルではありません", input))?),
+        None => input.strip_prefix('#').and_then(|name| voice_channel_named(guild, name))
+    };
+    match (input.to_lowercase().as_str(), channel) {
+        ("disconnect" | "切断", _) => Ok(Action::Disconnect),
+        ("afk", _) => Ok(Action::Afk),
+        (_, Some(c)) => Ok(Action::Move(c)),
+        _ => Err("切断時の動作は disconnect / afk / #移動先のボイスチャンネル で指定してください".to_string())
+    }
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command.name("schedule").description("入力欄で対象や理由をまとめて指定して切断予約します")
+}
+
+pub fn register_user_menu(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command.name(USER_MENU).kind(CommandType::User)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::json::{json, prelude::from_value};
+
+    const ME: UserId = UserId(2);
+
+    // モデルは#[non_exhaustive]なのでJSONから作る
+    // ボイスチャンネル「lobby」(10)、テキストチャンネル「chat」(11)、ロール「night」(20)、ユーザー「alice」(3)
+    fn guild() -> Guild {
+        let channel = |id: u64, name: &str, kind: u8| json!({
+            "id": id.to_string(), "guild_id": "1", "name": name, "type": kind, "position": 0,
+            "permission_overwrites": [], "nsfw": false
+        });
+        let role = |id: u64, name: &str| json!({
+            "id": id.to_string(), "guild_id": "1", "name": name, "color": 0, "hoist": false, "managed": false,
+            "mentionable": true, "permissions": "0", "position": 1
+        });
+        from_value(json!({
+            "id": "1", "name": "test", "owner_id": "2", "afk_timeout": 300, "default_message_notifications": 0,
+            "explicit_content_filter": 0, "mfa_level": 0, "verification_level": 0, "nsfw_level": 0,
+            "system_channel_flags": 0, "preferred_locale": "ja", "joined_at": "2024-01-01T00:00:00Z",
+            "large": false, "member_count": 2, "emojis": [], "features": [], "presences": [], "stickers": [],
+            "channels": [channel(10, "lobby", 2), channel(11, "chat", 0)],
+            "roles": [role(1, "@everyone"), role(20, "night")],
+            "members": [{
+                "user": { "id": "3", "username": "alice", "discriminator": "0001", "avatar": null },
+                "roles": ["20"], "joined_at": "2024-01-01T00:00:00Z", "deaf": false, "mute": false
+            }],
+            "voice_states": [{
+                "channel_id": "10", "user_id": "2", "session_id": "s", "deaf": false, "mute": false,
+                "self_deaf": false, "self_mute": false, "self_video": false, "suppress": false
+            }]
+        })).unwrap()
+    }
+
+    #[test]
+    fn targets_from_mentions_and_names() {
+        let guild = guild();
+        assert_eq!(parse_targets(&guild, "<@3> <@!3>, <@&20>、<#10>", ME),
+                   Ok(vec![Target::User(UserId(3)), Target::Role(RoleId(20)), Target::Channel(ChannelId(10))]));
+        assert_eq!(parse_targets(&guild, "@alice @night #lobby", ME),
+                   Ok(vec![Target::User(UserId(3)), Target::Role(RoleId(20)), Target::Channel(ChannelId(10))]));
+        assert_eq!(parse_targets(&guild, "me サーバー vc", ME),
+                   Ok(vec![Target::User(ME), Target::Server, Target::Channel(ChannelId(10))]));
+        // 未入力なら実行者
+        assert_eq!(parse_targets(&guild, "", ME), Ok(vec![Target::User(ME)]));
+    }
+
+    #[test]
+    fn targets_reject_unknown_and_text_channels() {
+        let guild = guild();
+        assert!(parse_targets(&guild, "<#11>", ME).unwrap_err().contains("ボイスチャンネルではありません"));
+        assert!(parse_targets(&guild, "#chat", ME).unwrap_err().contains("見つかりません"));
+        assert!(parse_targets(&guild, "@bob", ME).unwrap_err().contains("bob"));
+        assert!(parse_targets(&guild, "everyone", ME).unwrap_err().contains("everyone"));
+        assert!(parse_targets(&guild, "vc", UserId(3)).is_err());
+    }
+
+    #[test]
+    fn actions() {
+        let guild = guild();
+        assert_eq!(parse_action(&guild, "disconnect"), Ok(Action::Disconnect));
+        assert_eq!(parse_action(&guild, "切断"), Ok(Action::Disconnect));
+        assert_eq!(parse_action(&guild, "AFK"), Ok(Action::Afk));
+        assert_eq!(parse_action(&guild, "<#10>"), Ok(Action::Move(ChannelId(10))));
+        assert_eq!(parse_action(&guild, "#lobby"), Ok(Action::Move(ChannelId(10))));
+        assert!(parse_action(&guild, "<#11>").is_err());
+        assert!(parse_action(&guild, "#chat").is_err());
+        assert!(parse_action(&guild, "kick").is_err());
+    }
+}
@@ -2,6 +2,7 @@ use serenity::{
     builder::CreateApplicationCommand,
     model::{
         prelude::Mention,
+        guild::Guild,
         application::interaction::{
             application_command::ApplicationCommandInteraction,
             InteractionResponseType
@@ -9,10 +10,38 @@ use serenity::{
     },
     prelude::*
 };
-use std::sync::Arc;
-use crate::job::EventType;
+use std::{sync::Arc, collections::HashMap};
+use crate::job::{Job, Reservation, GuildSetting, Action};
+use crate::actions::{disconnect::Disconnect, reminder::{Reminder, ReminderParams}};
 use crate::SqliteDatabase;
 
+// 対象、繰り返し、切断時の動作（リマインダーはメッセージ）、ギルドの設定による予約かどうか、理由
+fn describe_reservation(reservation: &Reservation, job: &Job, setting: &GuildSetting, guild: Option<&Guild>) -> String {
+    let mut description = reservation.target().mention();
+    if let Some(schedule) = reservation.schedule() {
+        description += &format!(" 🔁 {}", schedule.describe());
+    }
+    if job.is(Reminder::NAME) {
+        let params = ReminderParams::parse(job.params.as_deref());
+        description += &format!(" 🔔 {}", params.message());
+        if params.always {
+            description += " (通話外も通知)";
+        }
+        return description;
+    }
+    let action = reservation.action(setting);
+    if action != Action::Disconnect {
+        description += &format!(" ➡ {}", guild.map_or_else(|| action.describe(), |guild| action.describe_in(guild)));
+    }
+    if let Some(policy) = reservation.policy {
+        description += &format!(" ({})", policy.describe());
+    }
+    if let Some(reason) = reservation.reason.as_ref() {
+        description += &format!(" 📝 {}", reason);
+    }
+    description
+}
+
 pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
     let guild_id = command.guild_id.unwrap();
     let database = {
@@ -21,6 +50,14 @@ pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
     };
 
     let jobs = database.get_guild_jobs(guild_id).await.unwrap();
+    let setting = database.get_guild_setting(guild_id).await.unwrap();
+    let guild = ctx.cache.guild(guild_id);
+    let lockouts = database.get_guild_lockouts(guild_id).await.unwrap();
+    let reservations: HashMap<i64, Reservation> = database.get_guild_reservations(guild_id).await
+        .unwrap()
+        .into_iter()
+        .map(|reservation| (reservation.id, reservation))
+        .collect();
 
     command
         .create_interaction_response(&ctx.http, |response| {
@@ -29,14 +66,23 @@ pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
                 .interaction_response_data(|message|
                     message.embed(|embed| {
                         jobs.iter()
-                            .filter(|job| job.event_type == EventType::Disconnect)
+                            .filter(|job| job.is(Disconnect::NAME) || job.is(Reminder::NAME))
                             .fold(embed
                                     .title("射殺予定")
                                     .description("snipebotの通話切断予定表"),
                                     |e, job|
-                                    e.field(format!("<t:{0}:t> <t:{0}:d>", job.timestamp()),
-                                            Mention::from(job.userid()),
+                                    e.field(format!("#{1} <t:{0}:t> <t:{0}:d>", job.timestamp(), job.reservation_id),
+                                            match reservations.get(&job.reservation_id) {
+                                                Some(reservation) => describe_reservation(reservation, job, &setting, guild.as_ref()),
+                                                None => Mention::from(job.userid()).to_string()
+                                            },
                                             false)
+                        );
+                        // ロックアウト中のユーザーと再参加を試みた回数
+                        lockouts.iter().fold(embed, |e, lockout|
+                            e.field(format!("🔒 <t:{}:t>まで", lockout.timestamp()),
+                                    format!("{} 再参加 {}回", Mention::from(lockout.userid()), lockout.attempts),
+                                    false)
                         )
                     })
                 )
@@ -1,210 +1,467 @@
 use serenity::{
     builder::CreateApplicationCommand,
     model::{
-        mention::Mention,
+        guild::Guild,
+        channel::ChannelType,
         id::{GuildId, UserId},
         application::{
             command::CommandOptionType,
-            interaction::application_command::ApplicationCommandInteraction,
+            interaction::{
+                application_command::{ApplicationCommandInteraction, CommandDataOption},
+                autocomplete::AutocompleteInteraction,
+                message_component::MessageComponentInteraction,
+                InteractionResponseType,
+            },
         }
     },
     prelude::*
 };
-use std::{sync::{Arc, atomic::Ordering}, collections::HashSet};
-use chrono::{Utc, Duration, FixedOffset, Timelike, NaiveDateTime};
-use regex::{Regex, Match};
-use tokio::sync::OnceCell;
-use crate::job::EventType;
-use crate::{SqliteDatabase, JobRunner};
+use std::sync::Arc;
+use chrono::{Utc, Duration, NaiveDateTime};
+use chrono_tz::Tz;
+use crate::SqliteDatabase;
+use crate::time_expr::{TimeExpr, parse_duration};
+use crate::job::{Target, ReservationOptions, SnipeRequest, SnipeDraft, Action, ESCALATION};
+use crate::actions::mute::{Mute, Deafen};
+use crate::schedule::{Recurrence, Repeat, Schedule, LeadTimes, describe_duration};
 use crate::commands::utils::*;
 
-static RE_TIME: OnceCell<Regex> = OnceCell::const_new();
-
-pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
-    let re_time = RE_TIME.get_or_init(|| async {
-        Regex::new(r"(?:(?P<hour>\d{1,2})(?:時間|時|:|：|hours|hour|h|Hours|Hour|H|\s^@))?(?:(?P<minute>\d{1,2})(?:分|mins|min|m|Mins|Min|M|))?").unwrap()}).await;
-    let time = string_option_ref(&command.data.options, "time").unwrap();
-    let caps = re_time.captures(time).unwrap();
-    let h_opt = caps.name("hour");
-    let m_opt = caps.name("minute");
-
-    if h_opt.is_none() && m_opt.is_none() {
-        command
-            .create_interaction_response(&ctx.http, |response| {
-                response
-                    .interaction_response_data(|message| {
-                        message
-                            .components(|c| c)
-                            .content("時間/時刻を認識できません")
-                    })
-            })
-            .await
-            .unwrap();
+// 指定方法に応じて予約時刻を求める
+pub fn reservation_time(time: &str, snipe_type: &str, tz: Tz, horizon: Duration) -> Result<NaiveDateTime, String> {
+    match snipe_type {
+        "at" => TimeExpr::parse(time)
+            .and_then(|expr| expr.resolve(tz, Utc::now()))
+            .map_err(|why| why.help()),
+        "in" => parse_duration(time, horizon)
+            .map(|duration| Utc::now().naive_utc() + duration)
+            .map_err(|why| why.help()),
+        _ => panic!("unexpected SnipeType.")
+    }
+}
 
-        return;
+// 時刻/時間のどちらとしても読めない入力のエラー（時間として読めていれば時間のエラー）
+pub fn validate_time(time: &str, horizon: Duration) -> Result<(), String> {
+    match (parse_duration(time, horizon), TimeExpr::parse(time)) {
+        (Ok(_), _) | (_, Ok(_)) => Ok(()),
+        (Err(why), Err(_)) if !why.is_syntax() => Err(why.help()),
+        (Err(_), Err(why)) => Err(why.help()),
     }
+}
 
-    let types = string_option_ref(&command.data.options, "type");
-    let opt_user = user_option_ref(&command.data.options, "user");
-    let opt_role = role_option_ref(&command.data.options, "role");
+// 候補から選ばれた値は "at:<入力>" / "in:<入力>" の形で指定方法を含む
+pub fn split_choice(time: &str) -> (Option<&'static str>, &str) {
+    for snipe_type in [SnipeType::Absolute, SnipeType::Relative] {
+        let prefix = snipe_type.custom_id();
+        if let Some(rest) = time.strip_prefix(prefix).and_then(|t| t.strip_prefix(':')) {
+            return (Some(prefix), rest);
+        }
+    }
+    (None, time)
+}
 
-    let guild_id = command.guild_id.unwrap();
-    let user_id = command.user.id;
+// 入力中の時刻/時間を解釈した候補 (名前, 値)
+fn time_candidates(input: &str, types: Option<&str>, tz: Tz, horizon: Duration) -> Vec<(String, String)> {
+    let now = Utc::now();
+    let today = now.with_timezone(&tz).date_naive();
+    let describe_local = |naive_utc: NaiveDateTime| {
+        let local = naive_utc.and_utc().with_timezone(&tz);
+        let day = match (local.date_naive() - today).num_days() {
+            0 => "今日".to_string(),
+            1 => "明日".to_string(),
+            _ => local.format("%m/%d").to_string(),
+        };
+        format!("{} {}", day, local.format("%H:%M"))
+    };
 
-    let mut target_userids: HashSet<UserId> = HashSet::new();
-    if let Some(u) = opt_user {
-        target_userids.insert(u.id);
+    let mut candidates = Vec::new();
+    let mut errors = Vec::new();
+    if types != Some("in") {
+        match TimeExpr::parse(input).and_then(|expr| expr.resolve(tz, now)) {
+            Ok(t) => candidates.push((
+                format!("{} {} ({}) — {}後", SnipeType::Absolute.emoji(), describe_local(t), tz.name(),
+                        describe_duration(t - now.naive_utc())),
+                format!("at:{}", input),
+            )),
+            Err(why) => errors.push(why.to_string()),
+        }
     }
-    if let Some(r) = opt_role {
-        let guild_members = r.guild_id.members(&ctx.http, None, None).await.unwrap();
-        guild_members.iter().for_each(|member| {
-            if member.roles.contains(&r.id) {
-                target_userids.insert(member.user.id);
-            }
-        });
+    if types != Some("at") {
+        match parse_duration(input, horizon) {
+            Ok(d) => candidates.push((
+                format!("{} {}後 ({})", SnipeType::Relative.emoji(), describe_duration(d),
+                        describe_local(now.naive_utc() + d)),
+                format!("in:{}", input),
+            )),
+            // 時刻として読める可能性がある書式エラーは時刻側のエラーを見せる
+            Err(why) if !why.is_syntax() || types == Some("in") => errors.push(why.to_string()),
+            Err(_) => {},
+        }
     }
-    if target_userids.is_empty() {
-        target_userids.insert(user_id);
+
+    // どちらとしても読めなければ、理由をそのまま候補として見せる
+    if candidates.is_empty() {
+        let reason = errors.last().cloned().unwrap_or_default();
+        candidates.push((format!("⚠️ {}", reason), input.to_string()));
     }
+    candidates
+}
 
+pub async fn autocomplete(ctx: Arc<Context>, autocomplete: &AutocompleteInteraction) {
+    let guild_id = autocomplete.guild_id.unwrap();
+    let user_id = autocomplete.user.id;
     let database = {
         let data_read = ctx.data.read().await;
         data_read.get::<SqliteDatabase>().unwrap().clone()
     };
-    let is_loop_running = {
+
+    let option_value = |name: &str| autocomplete.data.options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .map(|value| value.to_string().trim_matches('"').to_owned());
+    let input = option_value("time").unwrap_or_default();
+    let (_, input) = split_choice(input.trim());
+
+    // 繰り返し予約は時刻指定のみ
+    let types = match option_value("type") {
+        Some(t) => Some(t),
+        None if option_value("repeat").is_some() => Some("at".to_string()),
+        None => None,
+    };
+
+    let tz = database.get_timezone(user_id, guild_id).await.unwrap();
+    let horizon = database.get_guild_setting(guild_id).await.unwrap().max_horizon();
+    let candidates = if input.is_empty() {
+        ["30m", "1h", "23:00"].iter()
+            .flat_map(|example| time_candidates(example, types.as_deref(), tz, horizon))
+            .filter(|(name, _)| !name.starts_with('⚠'))
+            .collect()
+    } else if input.chars().count() > 90 {
+        Vec::new()
+    } else {
+        time_candidates(input, types.as_deref(), tz, horizon)
+    };
+
+    autocomplete
+        .create_autocomplete_response(&ctx.http, |response| {
+            candidates.into_iter()
+                .map(|(name, value)| (name.chars().take(100).collect::<String>(), value))
+                .fold(response, |r, (name, value)| r.add_string_choice(name, value))
+        })
+        .await
+        .unwrap_or_else(|why| println!("cannot respond to autocomplete: {}", why));
+}
+
+async fn reply(ctx: &Context, command: &ApplicationCommandInteraction, content: &str) {
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .interaction_response_data(|message| {
+                    message
+                        .components(|c| c)
+                        .content(content)
+                })
+        })
+        .await
+        .unwrap();
+}
+
+// ボタンを押したメッセージを書き換える
+async fn update(ctx: &Context, component: &MessageComponentInteraction, content: &str) {
+    component
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|message| {
+                    message
+                        .components(|c| c)
+                        .content(content)
+                })
+        })
+        .await
+        .unwrap_or_else(|why| println!("cannot respond to button: {}", why));
+}
+
+// 指定方法以外のオプションを読み取る
+async fn snipe_request(ctx: &Context, options: &[CommandDataOption], time: &str,
+                       guild_id: GuildId, user_id: UserId) -> Result<SnipeRequest, String> {
+    Ok(SnipeRequest {
+        time: time.to_string(),
+        recurrence: string_option_ref(options, "repeat").map(|r| Recurrence::parse(r)).transpose()?,
+        lead_times: string_option_ref(options, "warn").map(|w| LeadTimes::parse(w)).transpose()?,
+        lockout: string_option_ref(options, "lockout").cloned(),
+        action: action_option(options)?,
+        escalate: bool_option_ref(options, "escalate").copied().unwrap_or(false),
+        reason: None,
+        targets: collect_targets(ctx, options, guild_id, user_id).await?.into_iter().collect(),
+    })
+}
+
+pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
+    let (chosen, time) = split_choice(string_option_ref(&command.data.options, "time").unwrap());
+    let guild_id = command.guild_id.unwrap();
+    let user_id = command.user.id;
+
+    let request = match snipe_request(&ctx, &command.data.options, time, guild_id, user_id).await {
+        Ok(r) => r,
+        Err(why) => {
+            reply(&ctx, command, &why).await;
+            return;
+        }
+    };
+
+    // 繰り返し予約は時刻指定のみ（候補から選んだ場合は候補の指定方法を優先する）
+    let types = match chosen.or(string_option_ref(&command.data.options, "type").map(|t| t.as_str())) {
+        Some("in") if request.recurrence.is_some() => {
+            reply(&ctx, command, "繰り返し予約は時刻指定 (type:at) のみ対応しています").await;
+            return;
+        },
+        None if request.recurrence.is_some() => Some("at"),
+        t => t
+    };
+
+    let database = {
         let data_read = ctx.data.read().await;
-        data_read.get::<JobRunner>().unwrap().clone()
+        data_read.get::<SqliteDatabase>().unwrap().clone()
     };
 
     let snipe_type = match types {
-        Some(t) => t.to_owned(),
+        Some(t) => t,
         None => {
-            let _ = command.defer(&ctx.http).await;
+            // 指定方法が決まる前に、どちらの方法でも解釈できない入力は弾く
+            let setting = database.get_guild_setting(guild_id).await.unwrap();
+            if let Err(why) = validate_time(time, setting.max_horizon()) {
+                reply(&ctx, command, &why).await;
+                return;
+            }
 
-            let msg = command
-                .create_followup_message(&ctx.http, |response| {
+            // 入力を保存してボタンで指定方法を選ぶ（選択はselect_typeで受け付ける）
+            let draft_id = match database.insert_snipe_draft(user_id, guild_id, &request).await {
+                Ok(id) => id,
+                Err(why) => {
+                    println!("{:?}", why);
+                    reply(&ctx, command, "予約の受付に失敗しました").await;
+                    return;
+                }
+            };
+            command
+                .create_interaction_response(&ctx.http, |response| {
                     response
-                        .content("時間指定方法の選択")
-                        .components(|c| c.add_action_row(SnipeType::action_row()))
+                        .interaction_response_data(|message| {
+                            message
+                                .content(format!("時間指定方法の選択（{}分以内）", SnipeDraft::TTL_MINUTES))
+                                .components(|c| c.add_action_row(SnipeType::action_row(draft_id)))
+                        })
                 })
                 .await
                 .unwrap();
+            return;
+        }
+    };
 
-            let mci = match msg.await_component_interaction(&*ctx)
-                .author_id(user_id)
-                .timeout(std::time::Duration::from_secs(30))
-                .await {
-                    Some(ci) => {
-                        ci
-                    },
-                    None => {
-                        command
-                            .edit_original_interaction_response(&ctx.http, |message| {
-                                message
-                                    .components(|c| c)
-                                    .content("タイムアウトしました")
-                            })
-                            .await
-                            .unwrap();
-                        return;
-                    }
-                };
+    let content = reserve(&ctx, &database, &request, guild_id, user_id, snipe_type).await
+        .unwrap_or_else(|why| why);
+    reply(&ctx, command, &content).await;
+}
 
-            mci.data.custom_id.to_owned()
-        }
+// 指定方法/確定のボタンが押されたら、保存した入力で予約する
+pub async fn select_type(ctx: Arc<Context>, component: &MessageComponentInteraction) {
+    let Some((draft_id, snipe_type)) = SnipeType::from_custom_id(&component.data.custom_id) else {
+        return;
+    };
+    let database = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<SqliteDatabase>().unwrap().clone()
     };
 
-    let target_datetime: NaiveDateTime = match snipe_type.as_str() {
-        "at" => {
-            let guild_setting = database.get_guild_setting(guild_id).await.unwrap();
-            absolute_datetime(h_opt, m_opt, guild_setting.utc_offset)
-        },
-        "in" => {
-            relative_datetime(h_opt, m_opt)
+    let draft = match database.get_snipe_draft(draft_id).await {
+        Ok(Some(d)) => d,
+        Ok(None) => {
+            update(&ctx, component, "この予約は期限切れか、既に受け付けています").await;
+            return;
         },
-        _ => panic!("unexpected SnipeType.")
+        Err(why) => {
+            println!("{:?}", why);
+            return;
+        }
     };
 
-    let target_users_str: String = target_userids.iter().map(|&ui| Mention::from(ui).to_string()).collect();
-    for u in target_userids.into_iter() {
-        add_job(database.clone(), target_datetime, u, guild_id).await;
-    }
-
-    if types.is_some() {
-        command
+    if draft.userid() != component.user.id {
+        component
             .create_interaction_response(&ctx.http, |response| {
                 response
                     .interaction_response_data(|message| {
                         message
-                            .components(|c| c)
-                            .content(format!("{0}を<t:{1}:T> (<t:{1}:R>)に切断します",
-                                            target_users_str, target_datetime.timestamp()))
+                            .ephemeral(true)
+                            .content("予約したユーザーのみ選択できます")
                     })
             })
             .await
-            .unwrap();
-    } else {
-        command
-            .edit_original_interaction_response(&ctx.http, |message| {
-                message
-                    .components(|c| c)
-                    .content(format!("{0}を<t:{1}:T> (<t:{1}:R>)に切断します",
-                                    target_users_str, target_datetime.timestamp()))
-            })
-            .await
-            .unwrap();
+            .unwrap_or_else(|why| println!("cannot respond to button: {}", why));
+        return;
     }
 
-
-    if let Ok(_) = is_loop_running.compare_exchange(
-        false, true,
-        Ordering::Release, Ordering::Relaxed)
-    {
-        println!("start loop");
-        JobRunner::run(ctx).await;
+    match database.delete_snipe_draft(draft.id).await {
+        Ok(true) => {},
+        Ok(false) => return,
+        Err(why) => {
+            println!("{:?}", why);
+            return;
+        }
     }
-}
 
-fn relative_datetime(h_opt: Option<Match>, m_opt: Option<Match>) -> NaiveDateTime {
-    let hour: u32 = if let Some(h) = h_opt { h.as_str().parse().unwrap() } else { 0 };
-    let minute: u32 = if let Some(m) = m_opt { m.as_str().parse().unwrap() } else { 0 };
-    let datetime_utc = Utc::now() + Duration::hours(hour.into()) + Duration::minutes(minute.into());
+    let content = match (snipe_type, draft.is_expired(Utc::now().naive_utc())) {
+        (None, _) => "キャンセルしました".to_string(),
+        (_, true) => "タイムアウトしました".to_string(),
+        (Some(snipe_type), false) =>
+            reserve(&ctx, &database, &draft.request(), draft.guildid(), draft.userid(), snipe_type).await
+                .unwrap_or_else(|why| why)
+    };
+    update(&ctx, component, &content).await;
+}
 
-    datetime_utc.naive_utc()
+// 予約する内容（確定前の確認にも使う）
+pub struct Plan {
+    target_datetime: NaiveDateTime,
+    repeat: Option<Repeat>,
+    used_tz: Option<Tz>,
+    lockout_until: Option<NaiveDateTime>,
+    options: ReservationOptions,
+    // ギルドの設定で補った事前通知/切断時の動作
+    lead_times: LeadTimes,
+    action: Action,
 }
 
-fn absolute_datetime(h_opt: Option<Match>, m_opt: Option<Match>, utc_offset: i32) -> NaiveDateTime {
-    let local_now = Utc::now().with_timezone(&FixedOffset::east_opt(3600 * utc_offset).unwrap());
-    let hour: u32 = if let Some(h) = h_opt { h.as_str().parse().unwrap() } else { local_now.hour() };
-    let minute: u32 = if let Some(m) = m_opt { m.as_str().parse().unwrap() } else { local_now.minute() };
-    let mut tmp_datetime = local_now.naive_local().date().and_hms_opt(hour, minute, 0).unwrap();
+impl Plan {
+    pub async fn new(database: &SqliteDatabase, request: &SnipeRequest,
+                     guild_id: GuildId, user_id: UserId, snipe_type: &str) -> Result<Self, String> {
+        let setting = database.get_guild_setting(guild_id).await
+            .map_err(|_| "サーバーの設定の取得に失敗しました".to_string())?;
+        let tz = database.get_timezone(user_id, guild_id).await
+            .map_err(|_| "タイムゾーンの取得に失敗しました".to_string())?;
+        let mut target_datetime = reservation_time(&request.time, snipe_type, tz, setting.max_horizon())?;
+        let used_tz = (snipe_type == "at").then_some(tz);
 
-    tmp_datetime -= Duration::hours(utc_offset.into());
-    if h_opt.is_none() && local_now.minute() > tmp_datetime.minute() {
-        tmp_datetime += Duration::hours(1);
-    } else if local_now.naive_utc() >= tmp_datetime {
-        tmp_datetime += Duration::days(1);
+        // 繰り返し予約は指定時刻を現地時刻として保持し、初回はパターンに合う日に合わせる
+        let repeat = match (request.recurrence.clone(), used_tz) {
+            (Some(recurrence), Some(tz)) => {
+                let local_time = target_datetime.and_utc().with_timezone(&tz).time();
+                let repeat = Repeat { recurrence, local_time, tz };
+                target_datetime = repeat.first_on_or_after(target_datetime);
+                Some(repeat)
+            },
+            _ => None
+        };
+
+        // ロックアウトは切断時刻より後の最初の指定時刻まで（繰り返し予約では切断からの時間として保持）
+        let lockout_until = match request.lockout.as_deref() {
+            Some(lockout) => {
+                let mut until = TimeExpr::parse(lockout)
+                    .and_then(|expr| expr.resolve(tz, Utc::now()))
+                    .map_err(|why| format!("ロックアウトの時刻: {}", why.help()))?;
+                while until <= target_datetime {
+                    until += Duration::days(1);
+                }
+                Some(until)
+            },
+            None => None
+        };
+        // 段階的な切断では最後の段階で切断するので、ロックアウトはそこからの時間にする
+        let disconnect_datetime = match request.escalate {
+            true => target_datetime + Duration::minutes(ESCALATION[ESCALATION.len() - 1].1),
+            false => target_datetime
+        };
+
+        let options = ReservationOptions {
+            schedule: repeat.clone().map(Schedule::Repeat),
+            lead_times: request.lead_times.clone(),
+            lockout: lockout_until.map(|until| until - disconnect_datetime),
+            action: request.action,
+            escalate: request.escalate,
+            reason: request.reason.clone(),
+            ..Default::default()
+        };
+        Ok(Plan {
+            target_datetime,
+            repeat,
+            used_tz,
+            lockout_until,
+            options,
+            lead_times: request.lead_times.clone().unwrap_or_else(|| setting.lead_times()),
+            action: request.action.unwrap_or_else(|| setting.action()),
+        })
     }
 
-    tmp_datetime
+    pub fn describe(&self, targets: &[Target], guild: &Guild) -> String {
+        let target_users_str = targets.iter().map(|t| t.mention()).collect::<Vec<_>>().join(" ");
+        let mut content = format!("{0}を<t:{1}:T> (<t:{1}:R>)に切断します",
+                                  target_users_str, self.target_datetime.and_utc().timestamp());
+        if let Some(repeat) = self.repeat.as_ref() {
+            content += &format!("\n繰り返し: {} {}", repeat.recurrence.describe(), repeat.local_time.format("%H:%M"));
+        }
+        if let Some(tz) = self.used_tz {
+            content += &format!("\nタイムゾーン: {}", describe_timezone(tz));
+        }
+        content += &format!("\n事前通知: {}", self.lead_times.describe());
+        if self.action != Action::Disconnect {
+            content += &format!("\n切断時の動作: {}", self.action.describe_in(guild));
+        }
+        if self.options.escalate {
+            let stages: Vec<String> = ESCALATION.iter()
+                .map(|&(event_type, minutes)| {
+                    let stage = match event_type {
+                        Mute::NAME => "サーバーミュート".to_string(),
+                        Deafen::NAME => "スピーカーミュート".to_string(),
+                        _ => self.action.describe_in(guild)
+                    };
+                    match minutes {
+                        0 => format!("期限に{}", stage),
+                        _ => format!("{}分後に{}", minutes, stage)
+                    }
+                })
+                .collect();
+            content += &format!("\n段階的に切断: {}", stages.join(" → "));
+        }
+        if let Some(until) = self.lockout_until {
+            content += &format!("\nロックアウト: <t:{0}:T>まで再参加を切断します", until.and_utc().timestamp());
+        }
+        if let Some(reason) = self.options.reason.as_ref() {
+            content += &format!("\n理由: {}", reason);
+        }
+        content
+    }
 }
 
-async fn add_job(database: Arc<SqliteDatabase>, naive_utc: NaiveDateTime, user_id: UserId, guild_id: GuildId) {
-    // 切断前通知予約
-    let before3min = naive_utc - Duration::minutes(3);
-    if before3min.timestamp() > Utc::now().timestamp() {
-        if let Err(why) = database
-            .insert_job(before3min, user_id,
-                        guild_id, EventType::Notification3Min)
-            .await
-        {
+// 予約を登録し、返信する内容を返す
+async fn reserve(ctx: &Context, database: &Arc<SqliteDatabase>, request: &SnipeRequest,
+                 guild_id: GuildId, user_id: UserId, snipe_type: &str) -> Result<String, String> {
+    let guild = ctx.cache.guild(guild_id).ok_or_else(|| "サーバーの情報を取得できません".to_string())?;
+    let plan = Plan::new(database, request, guild_id, user_id, snipe_type).await?;
+    for target in request.targets.iter() {
+        add_job(database.clone(), plan.target_datetime, user_id, guild_id, target, &plan.options).await;
+    }
+    Ok(plan.describe(&request.targets, &guild))
+}
+
+// ユーザー指定の予約はそのユーザー、チャンネル/サーバー全体の予約は予約したユーザーのものとして登録
+pub async fn add_job(database: Arc<SqliteDatabase>, naive_utc: NaiveDateTime,
+                     user_id: UserId, guild_id: GuildId, target: &Target,
+                     options: &ReservationOptions) {
+    let user_id = match target {
+        Target::User(u) => *u,
+        _ => user_id
+    };
+
+    let reservation_id = match database.insert_reservation(user_id, guild_id, target, options).await {
+        Ok(id) => id,
+        Err(why) => {
             println!("{:?}", why);
+            return;
         }
-    }
+    };
 
-    // 切断予約
     if let Err(why) = database
-        .insert_job(naive_utc, user_id, guild_id, EventType::Disconnect)
+        .insert_disconnect_jobs(reservation_id, naive_utc, user_id, guild_id)
         .await
     {
         println!("{:?}", why);
@@ -218,6 +475,7 @@ pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicatio
                 .name("time")
                 .description("切断する時刻/切断するまでの時間")
                 .kind(CommandOptionType::String)
+                .set_autocomplete(true)
                 .required(true)
         })
         .create_option(|option| {
@@ -229,6 +487,52 @@ pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicatio
                 .add_string_choice("in", "in")
                 .required(false)
         })
+        .create_option(|option| {
+            option
+                .name("repeat")
+                .description("繰り返し (例: daily, weekdays, mon,wed,fri, 月水金, 3日ごと)")
+                .kind(CommandOptionType::String)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("warn")
+                .description("切断前通知のタイミング (例: 30m,10m,1m / なし)")
+                .kind(CommandOptionType::String)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("action")
+                .description("切断時の動作 (未指定ならギルドの設定)")
+                .kind(CommandOptionType::String)
+                .add_string_choice("切断", "disconnect")
+                .add_string_choice("指定チャンネルへ移動", "move")
+                .add_string_choice("AFKチャンネルへ移動", "afk")
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("move_to")
+                .description("移動先のボイスチャンネル")
+                .kind(CommandOptionType::Channel)
+                .channel_types(&[ChannelType::Voice, ChannelType::Stage])
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("escalate")
+                .description("段階的に切断します (期限にサーバーミュート→2分後にスピーカーミュート→5分後に切断)")
+                .kind(CommandOptionType::Boolean)
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("lockout")
+                .description("切断後、この時刻まで通話に戻ると再び切断します (例: 7:00)")
+                .kind(CommandOptionType::String)
+                .required(false)
+        })
         .create_option(|option| {
             option
                 .name("role")
@@ -243,4 +547,68 @@ pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicatio
                 .kind(CommandOptionType::User)
                 .required(false)
         })
+        .create_option(|option| {
+            option
+                .name("channel")
+                .description("ボイスチャンネルの全員を切断予約します")
+                .kind(CommandOptionType::Channel)
+                .channel_types(&[ChannelType::Voice, ChannelType::Stage])
+                .required(false)
+        })
+        .create_option(|option| {
+            option
+                .name("scope")
+                .description("参加中のボイスチャンネル/サーバー全体の全員を切断予約します")
+                .kind(CommandOptionType::String)
+                .add_string_choice("参加中のボイスチャンネル", "vc")
+                .add_string_choice("サーバー全体", "server")
+                .required(false)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn choice_prefix_round_trip() {
+        assert_eq!(split_choice("at:23:00"), (Some("at"), "23:00"));
+        assert_eq!(split_choice("in:1h30m"), (Some("in"), "1h30m"));
+        // 手で入力した値はそのまま（時刻の「:」を指定方法と取り違えない）
+        assert_eq!(split_choice("23:00"), (None, "23:00"));
+        assert_eq!(split_choice("at"), (None, "at"));
+        assert_eq!(split_choice("atx:23:00"), (None, "atx:23:00"));
+    }
+
+    #[test]
+    fn candidates_carry_their_type() {
+        let horizon = Duration::days(7);
+        let values = |input, types| time_candidates(input, types, Tz::UTC, horizon)
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect::<Vec<_>>();
+
+        assert_eq!(values("30m", None), vec!["in:30m"]);
+        // 時刻とも時間とも読める入力は両方を候補にし、指定方法が決まっていれば絞る
+        assert_eq!(values("23:00", None), vec!["at:23:00", "in:23:00"]);
+        assert_eq!(values("23:00", Some("at")), vec!["at:23:00"]);
+        assert_eq!(values("23:00", Some("in")), vec!["in:23:00"]);
+        assert_eq!(values("30m", Some("at")).len(), 1);
+        assert!(!values("30m", Some("at"))[0].contains(':'));
+
+        // 選ばれた候補から指定方法と入力を取り出せる
+        for value in values("1h", None) {
+            let (snipe_type, input) = split_choice(&value);
+            assert!(reservation_time(input, snipe_type.unwrap(), Tz::UTC, horizon).is_ok());
+        }
+    }
+
+    #[test]
+    fn unreadable_input_is_explained() {
+        let candidates = time_candidates("zzz", None, Tz::UTC, Duration::days(7));
+        assert_eq!(candidates.len(), 1);
+        assert!(candidates[0].0.starts_with('⚠'));
+        assert!(validate_time("zzz", Duration::days(7)).is_err());
+        assert!(validate_time("45m", Duration::days(7)).is_ok());
+    }
 }
@@ -0,0 +1,56 @@
+use serenity::{
+    builder::CreateApplicationCommand,
+    model::application::interaction::{
+        application_command::ApplicationCommandInteraction,
+        InteractionResponseType
+    },
+    prelude::*
+};
+use std::sync::Arc;
+use chrono::NaiveDateTime;
+use crate::{SqliteDatabase, JobRunner};
+
+fn format_time(naive_utc: Option<NaiveDateTime>) -> String {
+    match naive_utc {
+        Some(t) => format!("<t:{0}:d> <t:{0}:T> (<t:{0}:R>)", t.and_utc().timestamp()),
+        None => "なし".to_string()
+    }
+}
+
+pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
+    let (database, runner) = {
+        let data_read = ctx.data.read().await;
+        (data_read.get::<SqliteDatabase>().unwrap().clone(),
+         data_read.get::<JobRunner>().unwrap().clone())
+    };
+
+    let status = runner.status();
+    let pending = database.count_jobs().await.unwrap_or(-1);
+
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message|
+                    message.embed(|embed| {
+                        embed
+                            .title("JobRunnerの状態")
+                            .field("稼働中", if status.running { "はい" } else { "いいえ" }, true)
+                            .field("再起動回数", status.restarts, true)
+                            .field("待機中のジョブ", pending, true)
+                            .field("実行済み", status.executed, true)
+                            .field("スキップ (対象が通話外)", status.skipped, true)
+                            .field("失敗", status.failed, true)
+                            .field("起動時刻", format_time(status.started_at), false)
+                            .field("次のジョブ", format_time(status.next_job), false)
+                            .field("最後のエラー", status.last_error.as_deref().unwrap_or("なし"), false)
+                    })
+                )
+        })
+        .await
+        .unwrap_or_else(|why| println!("cannot respond to slash command: {}", why));
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command.name("status").description("切断予約の実行状況を表示します")
+}
@@ -1,7 +1,7 @@
 use serenity::{
     builder::CreateApplicationCommand,
     model::{
-        id::GuildId,
+        id::{GuildId, UserId},
         application::{
             command::CommandOptionType,
             interaction::{
@@ -12,40 +12,102 @@ use serenity::{
     },
     prelude::*
 };
-use chrono::FixedOffset;
+use chrono_tz::Tz;
 use std::sync::Arc;
 use crate::SqliteDatabase;
-use crate::commands::utils::int_option_ref;
+use crate::commands::utils::{string_option_ref, bool_option_ref, describe_timezone};
+use crate::commands::config::recreate_quiet_hours;
 
-async fn set_timezone(database: Arc<SqliteDatabase>, guild_id: GuildId, offset: i64) -> String {
-    let timezone = FixedOffset::east_opt(3600 * offset as i32).unwrap();
+fn parse_timezone(name: &str) -> Result<Tz, String> {
+    name.trim()
+        .parse()
+        .map_err(|_| format!("タイムゾーン「{}」を認識できません (例: Asia/Tokyo, America/St_Johns)", name))
+}
 
-    if let Ok(_) = database.update_guild_setting(guild_id, offset).await {
-        format!("{}に設定しました", timezone)
-    } else {
-        "タイムゾーンの設定に失敗しました".to_string()
+async fn set_timezone(database: Arc<SqliteDatabase>, guild_id: GuildId, user_id: UserId, name: &str) -> String {
+    let timezone = match parse_timezone(name) {
+        Ok(tz) => tz,
+        Err(why) => return why
+    };
+
+    if database.update_guild_setting(guild_id, timezone.name()).await.is_err() {
+        return "タイムゾーンの設定に失敗しました".to_string();
+    }
+    // 静粛時間の開始時刻の予約を新しいタイムゾーンで作り直す
+    if let Err(why) = recreate_quiet_hours(&database, guild_id, user_id).await {
+        println!("{:?}", why);
+        return format!("ギルドのタイムゾーンを{}に設定しましたが、静粛時間の予約の更新に失敗しました",
+                       describe_timezone(timezone));
     }
+    format!("ギルドのタイムゾーンを{}に設定しました", describe_timezone(timezone))
 }
 
 async fn get_timezone(database: Arc<SqliteDatabase>, guild_id: GuildId) -> String {
     if let Ok(setting) = database.get_guild_setting(guild_id).await {
-        format!("{}に設定されています", FixedOffset::east_opt(3600 * setting.utc_offset).unwrap())
+        format!("ギルドのタイムゾーンは{}に設定されています", describe_timezone(setting.tz()))
     } else {
         "タイムゾーンの取得に失敗しました".to_string()
     }
 }
 
+async fn set_user_timezone(database: Arc<SqliteDatabase>, user_id: UserId, name: &str) -> String {
+    let timezone = match parse_timezone(name) {
+        Ok(tz) => tz,
+        Err(why) => return why
+    };
+
+    if database.upsert_user_setting(user_id, timezone.name()).await.is_ok() {
+        format!("あなたのタイムゾーンを{}に設定しました", describe_timezone(timezone))
+    } else {
+        "タイムゾーンの設定に失敗しました".to_string()
+    }
+}
+
+async fn reset_user_timezone(database: Arc<SqliteDatabase>, user_id: UserId) -> String {
+    if database.delete_user_setting(user_id).await.is_ok() {
+        "あなたのタイムゾーン設定を削除しました（ギルドの設定を使用します）".to_string()
+    } else {
+        "タイムゾーンの削除に失敗しました".to_string()
+    }
+}
+
+async fn get_user_timezone(database: Arc<SqliteDatabase>, user_id: UserId) -> String {
+    match database.get_user_setting(user_id).await {
+        Ok(Some(setting)) => format!("あなたのタイムゾーンは{}に設定されています", describe_timezone(setting.tz())),
+        Ok(None) => "あなたのタイムゾーンは未設定です（ギルドの設定を使用します）".to_string(),
+        Err(_) => "タイムゾーンの取得に失敗しました".to_string()
+    }
+}
+
 pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
     let guild_id = command.guild_id.unwrap();
+    let user_id = command.user.id;
     let database = {
         let data_read = ctx.data.read().await;
         data_read.get::<SqliteDatabase>().unwrap().clone()
     };
 
-    let content = if let Some(offset) = int_option_ref(&command.data.options, "offset") {
-        set_timezone(database, guild_id, *offset).await
-    } else {
-        get_timezone(database, guild_id).await
+    let subcommand = command.data.options.first().unwrap();
+    let options = &subcommand.options;
+
+    let content = match subcommand.name.as_str() {
+        "guild" => {
+            if let Some(name) = string_option_ref(options, "name") {
+                set_timezone(database, guild_id, user_id, name).await
+            } else {
+                get_timezone(database, guild_id).await
+            }
+        },
+        "user" => {
+            if bool_option_ref(options, "reset") == Some(&true) {
+                reset_user_timezone(database, user_id).await
+            } else if let Some(name) = string_option_ref(options, "name") {
+                set_user_timezone(database, user_id, name).await
+            } else {
+                get_user_timezone(database, user_id).await
+            }
+        },
+        _ => panic!("unexpected subcommand.")
     };
 
     command
@@ -61,15 +123,38 @@ pub async fn run(ctx: Arc<Context>, command: &ApplicationCommandInteraction) {
 }
 
 pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
-    command.name("timezone").description("UTCからの時差を設定/表示します")
-        .create_option(|option| {
-            option
-                .name("offset")
-                .description("時差（h）")
-                .kind(CommandOptionType::Integer)
-                .min_int_value(-12)
-                .max_int_value(12)
-                .required(false)
+    command.name("timezone").description("タイムゾーンを設定/表示します")
+        .create_option(|subcommand| {
+            subcommand
+                .name("guild")
+                .description("ギルドのタイムゾーンを設定/表示します")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("name")
+                        .description("IANAタイムゾーン名 (例: Asia/Tokyo)")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+        })
+        .create_option(|subcommand| {
+            subcommand
+                .name("user")
+                .description("自分のタイムゾーンを設定/表示します（ギルドの設定より優先）")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("name")
+                        .description("IANAタイムゾーン名 (例: America/New_York)")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("reset")
+                        .description("自分の設定を削除してギルドの設定に戻します")
+                        .kind(CommandOptionType::Boolean)
+                        .required(false)
+                })
         })
 }
-
@@ -1,13 +1,21 @@
 use serenity::builder::{CreateButton, CreateActionRow};
 use serenity::model::prelude::Role;
+use serenity::model::channel::PartialChannel;
 use serenity::model::prelude::interaction::application_command::{
     CommandDataOption,
     CommandDataOptionValue,
 };
 use serenity::model::application::component::ButtonStyle;
 use serenity::model::user::User;
+use serenity::model::id::{GuildId, UserId};
+use serenity::prelude::Context;
+use std::collections::HashSet;
+use chrono::Utc;
+use chrono_tz::Tz;
 use std::fmt;
+use crate::job::{Target, Action};
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SnipeType {
     Relative,
     Absolute
@@ -23,7 +31,7 @@ impl fmt::Display for SnipeType {
 }
 
 impl SnipeType {
-    fn emoji(&self) -> char {
+    pub fn emoji(&self) -> char {
         match self {
             Self::Relative => '\u{23F2}',
             Self::Absolute => '\u{23F0}',
@@ -37,26 +45,132 @@ impl SnipeType {
         }
     }
 
-    fn custom_id(&self) -> &str {
+    pub fn custom_id(&self) -> &'static str {
         match self {
             Self::Relative => "in",
             Self::Absolute => "at",
         }
     }
 
-    fn button(&self) -> CreateButton {
+    // ボタンのcustom_idは "snipe:<snipe_draftのid>:<at|in|cancel>"（キャンセルはNone）
+    pub fn from_custom_id(custom_id: &str) -> Option<(i64, Option<&'static str>)> {
+        let (draft_id, choice) = custom_id.strip_prefix("snipe:")?.split_once(':')?;
+        let snipe_type = match choice {
+            "cancel" => None,
+            _ => Some([Self::Absolute, Self::Relative].into_iter()
+                .map(|t| t.custom_id())
+                .find(|&t| t == choice)?)
+        };
+        Some((draft_id.parse().ok()?, snipe_type))
+    }
+
+    fn button(&self, draft_id: i64) -> CreateButton {
+        let mut b = CreateButton::default();
+        b.custom_id(format!("snipe:{}:{}", draft_id, self.custom_id()));
+        b.emoji(self.emoji());
+        b.label(self);
+        b.style(self.style());
+        b
+    }
+
+    pub fn action_row(draft_id: i64) -> CreateActionRow {
+        let mut ar = CreateActionRow::default();
+        ar.add_button(SnipeType::Absolute.button(draft_id));
+        ar.add_button(SnipeType::Relative.button(draft_id));
+        ar
+    }
+
+    // 確定前の確認（解釈が1つなら「予約する」、複数なら指定方法ごとのボタン）
+    pub fn confirm_row(draft_id: i64, types: &[SnipeType]) -> CreateActionRow {
+        let mut ar = CreateActionRow::default();
+        match types {
+            [snipe_type] => {
+                let mut b = CreateButton::default();
+                b.custom_id(format!("snipe:{}:{}", draft_id, snipe_type.custom_id()));
+                b.label("予約する");
+                b.style(ButtonStyle::Success);
+                ar.add_button(b);
+            },
+            _ => for snipe_type in types {
+                ar.add_button(snipe_type.button(draft_id));
+            }
+        }
+        let mut cancel = CreateButton::default();
+        cancel.custom_id(format!("snipe:{}:cancel", draft_id));
+        cancel.label("キャンセル");
+        cancel.style(ButtonStyle::Secondary);
+        ar.add_button(cancel);
+        ar
+    }
+}
+
+// 切断前通知のDMに付けるボタン
+#[derive(Debug, PartialEq)]
+pub enum SnoozeAction {
+    Extend(i64),
+    DisconnectNow
+}
+
+impl fmt::Display for SnoozeAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+         match self {
+             Self::Extend(minutes) => write!(f, "+{}分", minutes),
+             Self::DisconnectNow => write!(f, "今すぐ切断"),
+         }
+    }
+}
+
+impl SnoozeAction {
+    pub const EXTENSIONS: [i64; 2] = [5, 15];
+
+    fn emoji(&self) -> char {
+        match self {
+            Self::Extend(_) => '\u{23F3}',
+            Self::DisconnectNow => '\u{1F44B}',
+        }
+    }
+
+    fn style(&self) -> ButtonStyle {
+        match self {
+            Self::Extend(_) => ButtonStyle::Primary,
+            Self::DisconnectNow => ButtonStyle::Danger,
+        }
+    }
+
+    // 再起動後も押せるよう予約のidを含める: "snooze:<予約のid>:<延長する分|now>"
+    fn custom_id(&self, reservation_id: i64) -> String {
+        match self {
+            Self::Extend(minutes) => format!("snooze:{}:{}", reservation_id, minutes),
+            Self::DisconnectNow => format!("snooze:{}:now", reservation_id),
+        }
+    }
+
+    pub fn from_custom_id(custom_id: &str) -> Option<(i64, Self)> {
+        let (reservation_id, action) = custom_id.strip_prefix("snooze:")?.split_once(':')?;
+        let action = match action {
+            "now" => Self::DisconnectNow,
+            minutes => Self::Extend(minutes.parse().ok()?)
+        };
+        Some((reservation_id.parse().ok()?, action))
+    }
+
+    fn button(&self, reservation_id: i64, disabled: bool) -> CreateButton {
         let mut b = CreateButton::default();
-        b.custom_id(self.custom_id());
+        b.custom_id(self.custom_id(reservation_id));
         b.emoji(self.emoji());
         b.label(self);
         b.style(self.style());
+        b.disabled(disabled);
         b
     }
 
-    pub fn action_row() -> CreateActionRow {
+    // 残りの延長回数/分を超える延長ボタンは押せなくする
+    pub fn action_row(reservation_id: i64, count_left: i64, minutes_left: i64) -> CreateActionRow {
         let mut ar = CreateActionRow::default();
-        ar.add_button(SnipeType::Absolute.button());
-        ar.add_button(SnipeType::Relative.button());
+        for minutes in Self::EXTENSIONS {
+            ar.add_button(Self::Extend(minutes).button(reservation_id, count_left <= 0 || minutes_left < minutes));
+        }
+        ar.add_button(Self::DisconnectNow.button(reservation_id, false));
         ar
     }
 }
@@ -91,6 +205,21 @@ pub fn int_option_ref<'a>(options: &'a [CommandDataOption], name: &str)
     }
 }
 
+pub fn bool_option_ref<'a>(options: &'a [CommandDataOption], name: &str)
+                             -> Option<&'a bool> {
+    let option_value = options
+        .iter()
+        .find(|&v| v.name == name)?
+        .resolved
+        .as_ref()?;
+
+    if let CommandDataOptionValue::Boolean(b) = option_value {
+        Some(b)
+    } else {
+        None
+    }
+}
+
 pub fn user_option_ref<'a>(options: &'a [CommandDataOption], name: &str)
                              -> Option<&'a User> {
     let option_value = options
@@ -120,3 +249,136 @@ pub fn role_option_ref<'a>(options: &'a [CommandDataOption], name: &str)
         None
     }
 }
+
+pub fn channel_option_ref<'a>(options: &'a [CommandDataOption], name: &str)
+                             -> Option<&'a PartialChannel> {
+    let option_value = options
+        .iter()
+        .find(|&v| v.name == name)?
+        .resolved
+        .as_ref()?;
+
+    if let CommandDataOptionValue::Channel(channel) = option_value {
+        Some(channel)
+    } else {
+        None
+    }
+}
+
+// action/move_toオプションの切断時の動作（未指定ならNone）
+// move_toは移動するときだけ指定できる（切断/AFKと同時に指定したら無視せずエラーにする）
+pub fn action_option(options: &[CommandDataOption]) -> Result<Option<Action>, String> {
+    let channel_id = channel_option_ref(options, "move_to").map(|c| c.id);
+    match (string_option_ref(options, "action").map(|a| a.as_str()), channel_id) {
+        (Some(action @ ("disconnect" | "afk")), Some(_)) =>
+            Err(format!("move_to は action が {} のときは指定できません", action)),
+        (Some("disconnect"), None) => Ok(Some(Action::Disconnect)),
+        (Some("afk"), None) => Ok(Some(Action::Afk)),
+        (Some("move") | None, Some(c)) => Ok(Some(Action::Move(c))),
+        (Some("move"), None) => Err("移動先のボイスチャンネル (move_to) を指定してください".to_string()),
+        _ => Ok(None)
+    }
+}
+
+pub fn describe_timezone(tz: Tz) -> String {
+    format!("{} (UTC{})", tz.name(), Utc::now().with_timezone(&tz).format("%:z"))
+}
+
+// user/role/channel/scopeオプションの切断対象を集める（未指定なら実行者）
+// ロール/チャンネル/サーバー全体は切断時点で通話中のユーザーに展開する
+pub async fn collect_targets(ctx: &Context, options: &[CommandDataOption], guild_id: GuildId, default: UserId)
+                     -> Result<HashSet<Target>, String> {
+    let mut targets: HashSet<Target> = HashSet::new();
+    if let Some(u) = user_option_ref(options, "user") {
+        targets.insert(Target::User(u.id));
+    }
+    if let Some(r) = role_option_ref(options, "role") {
+        targets.insert(Target::Role(r.id));
+    }
+    if let Some(c) = channel_option_ref(options, "channel") {
+        targets.insert(Target::Channel(c.id));
+    }
+    match string_option_ref(options, "scope").map(|s| s.as_str()) {
+        Some("vc") => {
+            let channel_id = ctx.cache.guild(guild_id)
+                .and_then(|guild| guild.voice_states.get(&default)?.channel_id)
+                .ok_or_else(|| "ボイスチャンネルに参加していません".to_string())?;
+            targets.insert(Target::Channel(channel_id));
+        },
+        Some("server") => {
+            targets.insert(Target::Server);
+        },
+        _ => {}
+    }
+    if targets.is_empty() {
+        targets.insert(Target::User(default));
+    }
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::json::{json, prelude::from_value};
+    use serenity::model::id::ChannelId;
+
+    // モデルは#[non_exhaustive]なのでJSONから作り、resolvedを埋める
+    fn option(name: &str, resolved: CommandDataOptionValue) -> CommandDataOption {
+        let mut option: CommandDataOption = from_value(json!({ "name": name, "type": 3 })).unwrap();
+        option.resolved = Some(resolved);
+        option
+    }
+
+    fn string(name: &str, value: &str) -> CommandDataOption {
+        option(name, CommandDataOptionValue::String(value.to_string()))
+    }
+
+    fn channel(name: &str, id: u64) -> CommandDataOption {
+        let channel = from_value(json!({ "id": id.to_string(), "type": 2 })).unwrap();
+        option(name, CommandDataOptionValue::Channel(channel))
+    }
+
+    #[test]
+    fn action_option_combinations() {
+        let lobby = ChannelId(10);
+        assert_eq!(action_option(&[]), Ok(None));
+        assert_eq!(action_option(&[string("action", "disconnect")]), Ok(Some(Action::Disconnect)));
+        assert_eq!(action_option(&[string("action", "afk")]), Ok(Some(Action::Afk)));
+        assert_eq!(action_option(&[string("action", "move"), channel("move_to", 10)]), Ok(Some(Action::Move(lobby))));
+        // move_toだけなら移動として扱う
+        assert_eq!(action_option(&[channel("move_to", 10)]), Ok(Some(Action::Move(lobby))));
+        assert!(action_option(&[string("action", "move")]).is_err());
+    }
+
+    #[test]
+    fn action_option_rejects_move_to_without_move() {
+        for action in ["disconnect", "afk"] {
+            let err = action_option(&[string("action", action), channel("move_to", 10)]).unwrap_err();
+            assert!(err.contains(action));
+        }
+    }
+
+    #[test]
+    fn snipe_custom_id_round_trip() {
+        for snipe_type in [SnipeType::Absolute, SnipeType::Relative] {
+            let custom_id = format!("snipe:7:{}", snipe_type.custom_id());
+            assert_eq!(SnipeType::from_custom_id(&custom_id), Some((7, Some(snipe_type.custom_id()))));
+        }
+        assert_eq!(SnipeType::from_custom_id("snipe:7:cancel"), Some((7, None)));
+        assert_eq!(SnipeType::from_custom_id("snipe:7:later"), None);
+        assert_eq!(SnipeType::from_custom_id("snipe:x:at"), None);
+        assert_eq!(SnipeType::from_custom_id("snooze:7:at"), None);
+    }
+
+    #[test]
+    fn snooze_custom_id_round_trip() {
+        for action in [SnoozeAction::Extend(5), SnoozeAction::Extend(15), SnoozeAction::DisconnectNow] {
+            let custom_id = action.custom_id(42);
+            assert_eq!(SnoozeAction::from_custom_id(&custom_id), Some((42, action)));
+        }
+        assert_eq!(SnoozeAction::from_custom_id("snooze:42"), None);
+        assert_eq!(SnoozeAction::from_custom_id("snooze:x:5"), None);
+        assert_eq!(SnoozeAction::from_custom_id("snooze:42:later"), None);
+        assert_eq!(SnoozeAction::from_custom_id("snipe:42:5"), None);
+    }
+}
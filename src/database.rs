@@ -1,9 +1,18 @@
-use chrono::NaiveDateTime;
-use serenity::model::id::{UserId, GuildId};
-use crate::job::{Job, EventType, GuildSetting};
+use chrono::{Utc, Duration, NaiveDateTime};
+use chrono_tz::Tz;
+use serenity::model::id::{UserId, GuildId, RoleId, ChannelId};
+use tokio::sync::Notify;
+use crate::job::{Job, GuildSetting, UserSetting, Reservation, ReservationOptions,
+                 Target, TargetKind, Lockout, Policy, VoiceSession, VoiceBudget,
+                 Action, ActionKind, MutedMember, SnipeRequest, SnipeDraft, ESCALATION,
+                 escalation_minutes};
+use crate::schedule::{Schedule, LeadTimes, QuietHours};
+use crate::actions::{disconnect::Disconnect, notification::Notification, reminder::Reminder};
 
 pub struct SqliteDatabase {
     database: sqlx::SqlitePool,
+    // ジョブの追加/変更/削除をJobRunnerに知らせる
+    job_changed: Notify,
 }
 
 impl SqliteDatabase {
@@ -20,38 +29,369 @@ impl SqliteDatabase {
 
         sqlx::migrate!("./migrations").run(&database).await.expect("Couldn't run database migrations");
 
-        SqliteDatabase { database }
+        SqliteDatabase { database, job_changed: Notify::new() }
     }
 
-    pub async fn pop_executables(&self) -> Result<Vec<Job>, sqlx::Error> {
-        sqlx::query_as!(
+    pub fn job_changed(&self) -> &Notify {
+        &self.job_changed
+    }
+
+    // 指定時刻を過ぎたジョブを取り出す
+    // 繰り返し予約の次回分を同じトランザクションで登録してから確定する（途中で失敗しても予約が失われない）
+    pub async fn pop_executables(&self)
+                                 -> Result<(sqlx::Transaction<'_, sqlx::Sqlite>, Vec<Job>), sqlx::Error> {
+        let now = Utc::now().naive_utc();
+
+        let mut tx = self.database.begin().await?;
+        let jobs = sqlx::query_as!(
             Job,
             r#"DELETE FROM job
-               WHERE naive_utc <= CURRENT_TIMESTAMP
-               RETURNING naive_utc, user_id, guild_id,
-                         event_type as "event_type!: EventType""#
+               WHERE naive_utc <= ?
+               RETURNING id as "id!", reservation_id, naive_utc, user_id, guild_id,
+                         CAST(event_type AS TEXT) as "event_type!: String", params"#,
+            now
             )
+            .fetch_all(&mut tx)
+            .await?;
+
+        // 取り出したジョブの予約は、実行時に対象や動作を読むため実行が終わるまで残す
+        Ok((tx, jobs))
+    }
+
+    // 期限切れのロックアウトを削除し、ロックアウトのために残していた予約のIDを返す
+    // （予約の削除は実行中のジョブがないことを確かめてから呼び出し元が行う）
+    pub async fn expire_lockouts(&self) -> Result<Vec<i64>, sqlx::Error> {
+        let now = Utc::now().naive_utc();
+
+        let expired = sqlx::query!("DELETE FROM lockout WHERE until <= ? RETURNING reservation_id", now)
             .fetch_all(&self.database)
+            .await?;
+        Ok(expired.into_iter().map(|lockout| lockout.reservation_id).collect())
+    }
+
+    // ジョブもロックアウトも残っていない予約を削除（繰り返し予約は次回分を登録するので残す）
+    pub async fn delete_finished_reservation(&self, reservation_id: i64)
+                                             -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        sqlx::query!("DELETE FROM reservation
+                      WHERE id=? AND recurrence IS NULL AND cron IS NULL
+                        AND id NOT IN (SELECT reservation_id FROM job)
+                        AND id NOT IN (SELECT reservation_id FROM lockout)",
+                     reservation_id)
+            .execute(&self.database)
+            .await
+    }
+
+    // 起動時に、停止中に期限を過ぎて実行されなかった予約をまとめて削除
+    pub async fn delete_finished_reservations(&self) -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        sqlx::query!("DELETE FROM reservation
+                      WHERE recurrence IS NULL AND cron IS NULL
+                        AND id NOT IN (SELECT reservation_id FROM job)
+                        AND id NOT IN (SELECT reservation_id FROM lockout)")
+            .execute(&self.database)
+            .await
+    }
+
+    pub async fn insert_reservation(&self,
+                        user_id: UserId,
+                        guild_id: GuildId,
+                        target: &Target,
+                        options: &ReservationOptions)
+                        -> Result<i64, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+        let (recurrence, local_time, timezone, cron) = match &options.schedule {
+            Some(Schedule::Repeat(r)) => (Some(r.recurrence.to_string()),
+                                          Some(r.local_time.format("%H:%M").to_string()),
+                                          Some(r.tz.name()),
+                                          None),
+            Some(Schedule::Cron(c)) => (None, None, Some(c.tz.name()), Some(c.pattern.as_str())),
+            None => (None, None, None, None)
+        };
+        let warnings = options.lead_times.as_ref().map(|l| l.to_string());
+        let target_kind = target.kind();
+        let target_id = target.id();
+        let lockout_minutes = options.lockout.map(|d| d.num_minutes());
+        let policy = options.policy;
+        let action = options.action.map(|a| a.kind());
+        let action_channel_id = options.action.and_then(|a| a.channel_id());
+        let escalate = options.escalate;
+        let reason = options.reason.as_deref();
+
+        let result = sqlx::query!(
+            "INSERT INTO reservation
+             (user_id, guild_id, recurrence, local_time, timezone, cron, warnings,
+              target_kind, target_id, lockout_minutes, policy, action, action_channel_id, escalate, reason)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            user_id, guild_id, recurrence, local_time, timezone, cron, warnings,
+            target_kind, target_id, lockout_minutes, policy, action, action_channel_id, escalate, reason
+            )
+            .execute(&self.database)
+            .await?;
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn get_reservation(&self, reservation_id: i64)
+                                 -> Result<Option<Reservation>, sqlx::Error> {
+        sqlx::query_as!(
+            Reservation,
+            "SELECT id, user_id, guild_id, recurrence, local_time, timezone, cron, warnings,
+                    snoozed_count, snoozed_minutes,
+                    target_kind as 'target_kind: TargetKind', target_id, lockout_minutes,
+                    policy as 'policy: Policy',
+                    action as 'action: ActionKind', action_channel_id, escalate, reason, scheduled_utc
+             FROM reservation WHERE id=?",
+            reservation_id)
+            .fetch_optional(&self.database)
+            .await
+    }
+
+    pub async fn get_guild_reservations(&self, guild_id: GuildId)
+                                        -> Result<Vec<Reservation>, sqlx::Error> {
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query_as!(
+            Reservation,
+            "SELECT id, user_id, guild_id, recurrence, local_time, timezone, cron, warnings,
+                    snoozed_count, snoozed_minutes,
+                    target_kind as 'target_kind: TargetKind', target_id, lockout_minutes,
+                    policy as 'policy: Policy',
+                    action as 'action: ActionKind', action_channel_id, escalate, reason, scheduled_utc
+             FROM reservation WHERE guild_id=?",
+            guild_id)
+            .fetch_all(&self.database)
+            .await
+    }
+
+    // 事前通知と、切断（段階的な切断では各段階）のジョブの時刻
+    async fn disconnect_job_times(&self, reservation_id: i64, naive_utc: NaiveDateTime, guild_id: GuildId)
+                                  -> Result<Vec<(NaiveDateTime, &'static str)>, sqlx::Error> {
+        let reservation = self.get_reservation(reservation_id).await?;
+        let lead_times = match reservation.as_ref().and_then(|r| r.lead_times()) {
+            Some(lead_times) => lead_times,
+            None => self.get_guild_setting(guild_id).await?.lead_times()
+        };
+
+        let now = Utc::now().naive_utc();
+        let mut jobs: Vec<(NaiveDateTime, &'static str)> = lead_times.durations()
+            .map(|lead| (naive_utc - lead, Notification::NAME))
+            .filter(|&(notify_at, _)| notify_at > now)
+            .collect();
+
+        if reservation.is_some_and(|r| r.escalate) {
+            jobs.extend(ESCALATION.iter().map(|&(event_type, minutes)| (naive_utc + Duration::minutes(minutes), event_type)));
+        } else {
+            jobs.push((naive_utc, Disconnect::NAME));
+        }
+        Ok(jobs)
+    }
+
+    async fn insert_jobs(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, reservation_id: i64,
+                         user_id: UserId, guild_id: GuildId, jobs: &[(NaiveDateTime, &str)])
+                         -> Result<(), sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        for (naive_utc, event_type) in jobs {
+            sqlx::query!(
+                "INSERT INTO job (reservation_id, naive_utc, user_id, guild_id, event_type)
+                 VALUES (?, ?, ?, ?, ?)",
+                reservation_id, naive_utc, user_id, guild_id, event_type
+                )
+                .execute(&mut *tx)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // 今回の発生の本来の時刻を記録する
+    async fn set_scheduled_utc(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, reservation_id: i64,
+                               naive_utc: NaiveDateTime) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE reservation SET scheduled_utc=? WHERE id=?",
+            naive_utc, reservation_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        Ok(())
+    }
+
+    // 切断予約と切断前通知を登録（通知時刻が過ぎている場合は通知を登録しない）
+    // 通知タイミングは予約ごとの指定を優先し、未指定ならギルドの設定を使う
+    // 段階的な切断ではnaive_utcを期限として各段階を登録する
+    pub async fn insert_disconnect_jobs(&self,
+                        reservation_id: i64,
+                        naive_utc: NaiveDateTime,
+                        user_id: UserId,
+                        guild_id: GuildId)
+                        -> Result<(), sqlx::Error> {
+        let mut tx = self.database.begin().await?;
+        self.insert_disconnect_jobs_in(&mut tx, reservation_id, naive_utc, user_id, guild_id).await?;
+        tx.commit().await?;
+
+        self.job_changed.notify_one();
+        Ok(())
+    }
+
+    // 呼び出し元のトランザクション内で登録する（繰り返し予約の次回分）
+    pub async fn insert_disconnect_jobs_in(&self,
+                        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+                        reservation_id: i64,
+                        naive_utc: NaiveDateTime,
+                        user_id: UserId,
+                        guild_id: GuildId)
+                        -> Result<(), sqlx::Error> {
+        let jobs = self.disconnect_job_times(reservation_id, naive_utc, guild_id).await?;
+        Self::insert_jobs(tx, reservation_id, user_id, guild_id, &jobs).await?;
+        Self::set_scheduled_utc(tx, reservation_id, naive_utc).await
+    }
+
+    // 切断予約と切断前通知を新しい時刻で作り直す（途中で失敗しても元の予約が残るよう1つのトランザクションで行う）
+    pub async fn reschedule_disconnect_jobs(&self,
+                        reservation_id: i64,
+                        naive_utc: NaiveDateTime,
+                        user_id: UserId,
+                        guild_id: GuildId)
+                        -> Result<(), sqlx::Error> {
+        let jobs = self.disconnect_job_times(reservation_id, naive_utc, guild_id).await?;
+
+        let mut tx = self.database.begin().await?;
+        sqlx::query!("DELETE FROM job WHERE reservation_id=?", reservation_id)
+            .execute(&mut tx)
+            .await?;
+        Self::insert_jobs(&mut tx, reservation_id, user_id, guild_id, &jobs).await?;
+        tx.commit().await?;
+
+        self.job_changed.notify_one();
+        Ok(())
+    }
+
+    pub async fn reschedule_reminder_job(&self, reservation_id: i64, naive_utc: NaiveDateTime)
+                                         -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let event_type = Reminder::NAME;
+
+        let result = sqlx::query!(
+            "UPDATE job SET naive_utc=? WHERE reservation_id=? AND event_type=?",
+            naive_utc, reservation_id, event_type
+            )
+            .execute(&self.database)
+            .await;
+
+        self.job_changed.notify_one();
+        result
+    }
+
+    // 切断（段階的な切断では最初の段階）の時刻
+    // 段階的な切断でミュートが済んだ後も同じ時刻になるよう、切断のジョブから求める
+    pub async fn get_deadline(&self, reservation_id: i64) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+        let Some(disconnect_at) = self.get_job_time(reservation_id, Disconnect::NAME).await? else {
+            return Ok(None);
+        };
+        let escalate = self.get_reservation(reservation_id).await?.is_some_and(|r| r.escalate);
+        Ok(Some(match escalate {
+            true => disconnect_at - Duration::minutes(escalation_minutes(Disconnect::NAME)),
+            false => disconnect_at
+        }))
+    }
+
+    // 上限の確認と加算を1つのUPDATEで行い、ボタンを連打しても上限を超えないようにする
+    // 上限に達していて延長できなければfalse
+    pub async fn add_snooze(&self, reservation_id: i64, minutes: i64, setting: &GuildSetting)
+                            -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE reservation
+             SET snoozed_count=snoozed_count+1, snoozed_minutes=snoozed_minutes+?
+             WHERE id=? AND snoozed_count<? AND snoozed_minutes+?<=?",
+            minutes, reservation_id, setting.snooze_count, minutes, setting.snooze_minutes
+            )
+            .execute(&self.database)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // 繰り返し予約は次回分の登録時に延長回数を戻す
+    pub async fn reset_snooze(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, reservation_id: i64)
+                              -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        sqlx::query!(
+            "UPDATE reservation SET snoozed_count=0, snoozed_minutes=0 WHERE id=?",
+            reservation_id
+            )
+            .execute(&mut *tx)
             .await
     }
 
-    pub async fn insert_job(&self,
+    // 任意の処理のジョブを登録する（paramsは処理ごとの形式で保存する）
+    pub async fn insert_action_job(&self,
+                        reservation_id: i64,
+                        naive_utc: NaiveDateTime,
+                        user_id: UserId,
+                        guild_id: GuildId,
+                        event_type: &str,
+                        params: Option<&str>)
+                        -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let mut tx = self.database.begin().await?;
+        let result = Self::insert_action_job_in(&mut tx, reservation_id, naive_utc, user_id, guild_id,
+                                                event_type, params).await?;
+        tx.commit().await?;
+
+        self.job_changed.notify_one();
+        Ok(result)
+    }
+
+    // 呼び出し元のトランザクション内で登録する（繰り返し予約の次回分）
+    pub async fn insert_action_job_in(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+                        reservation_id: i64,
                         naive_utc: NaiveDateTime,
                         user_id: UserId,
                         guild_id: GuildId,
-                        event_type: EventType)
+                        event_type: &str,
+                        params: Option<&str>)
                         -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
         let user_id = user_id.0 as i64;
         let guild_id = guild_id.0 as i64;
 
-        sqlx::query!(
+        let result = sqlx::query!(
             "INSERT INTO job
-             (naive_utc, user_id, guild_id, event_type)
-             SELECT $1, $2, $3, $4",
-            naive_utc, user_id, guild_id, event_type
+             (reservation_id, naive_utc, user_id, guild_id, event_type, params)
+             SELECT $1, $2, $3, $4, $5, $6",
+            reservation_id, naive_utc, user_id, guild_id, event_type, params
+            )
+            .execute(&mut *tx)
+            .await?;
+        Self::set_scheduled_utc(tx, reservation_id, naive_utc).await?;
+        Ok(result)
+    }
+
+    pub async fn get_job_time(&self,
+                        reservation_id: i64,
+                        event_type: &str)
+                        -> Result<Option<NaiveDateTime>, sqlx::Error> {
+        let result = sqlx::query!(
+            "SELECT naive_utc FROM job WHERE reservation_id=? AND event_type=?
+             ORDER BY naive_utc ASC LIMIT 1",
+            reservation_id, event_type
+            )
+            .fetch_optional(&self.database)
+            .await?;
+        Ok(result.map(|r| r.naive_utc))
+    }
+
+    pub async fn delete_reservation(&self,
+                        reservation_id: i64,
+                        user_id: UserId,
+                        guild_id: GuildId)
+                        -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        let result = sqlx::query!(
+            "DELETE FROM reservation WHERE id=? AND user_id=? AND guild_id=?",
+            reservation_id, user_id, guild_id
             )
             .execute(&self.database)
-            .await
+            .await;
+
+        self.job_changed.notify_one();
+        result
     }
 
     pub async fn delete_guild_jobs(&self,
@@ -61,12 +401,15 @@ impl SqliteDatabase {
         let user_id = user_id.0 as i64;
         let guild_id = guild_id.0 as i64;
 
-        sqlx::query!(
-            "DELETE FROM job WHERE user_id=? AND guild_id=?",
+        let result = sqlx::query!(
+            "DELETE FROM reservation WHERE user_id=? AND guild_id=? AND policy IS NULL",
             user_id, guild_id
             )
             .execute(&self.database)
-            .await
+            .await;
+
+        self.job_changed.notify_one();
+        result
     }
 
     pub async fn get_guild_jobs(&self, guild_id: GuildId)
@@ -75,8 +418,8 @@ impl SqliteDatabase {
 
         sqlx::query_as!(
             Job,
-            "SELECT naive_utc, user_id, guild_id,
-                    event_type as 'event_type!: EventType'
+            "SELECT id as 'id!', reservation_id, naive_utc, user_id, guild_id,
+                    CAST(event_type AS TEXT) as 'event_type!: String', params
              FROM job
              WHERE guild_id=?
              ORDER BY naive_utc ASC",
@@ -85,6 +428,32 @@ impl SqliteDatabase {
             .await
     }
 
+    pub async fn get_user_reservations(&self, user_id: UserId, guild_id: GuildId)
+                                   -> Result<Vec<Job>, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+        let (disconnect, reminder) = (Disconnect::NAME, Reminder::NAME);
+
+        sqlx::query_as!(
+            Job,
+            "SELECT id as 'id!', reservation_id, naive_utc, user_id, guild_id,
+                    CAST(event_type AS TEXT) as 'event_type!: String', params
+             FROM job
+             WHERE user_id=? AND guild_id=? AND event_type IN (?, ?)
+               AND reservation_id IN (SELECT id FROM reservation WHERE policy IS NULL)
+             ORDER BY naive_utc ASC",
+            user_id, guild_id, disconnect, reminder)
+            .fetch_all(&self.database)
+            .await
+    }
+
+    pub async fn next_job_time(&self) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+        let result = sqlx::query!("SELECT naive_utc FROM job ORDER BY naive_utc ASC LIMIT 1")
+            .fetch_optional(&self.database)
+            .await?;
+        Ok(result.map(|r| r.naive_utc))
+    }
+
     pub async fn count_jobs(&self) -> Result<i32, sqlx::Error> {
         let result = sqlx::query!("SELECT COUNT(*) as count FROM job")
             .fetch_one(&self.database)
@@ -92,26 +461,395 @@ impl SqliteDatabase {
         Ok(result.count)
     }
 
+    pub async fn insert_lockout(&self,
+                        reservation_id: i64,
+                        user_id: UserId,
+                        guild_id: GuildId,
+                        until: NaiveDateTime,
+                        channel_id: Option<ChannelId>)
+                        -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+        let channel_id = channel_id.map(|c| c.0 as i64);
+
+        // 実行中に予約が取り消されていたらロックアウトしない
+        sqlx::query!(
+            "INSERT INTO lockout (reservation_id, user_id, guild_id, until, channel_id)
+             SELECT $1, $2, $3, $4, $5 WHERE EXISTS (SELECT 1 FROM reservation WHERE id=$1)",
+            reservation_id, user_id, guild_id, until, channel_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    // 最も遅くまで続く有効なロックアウト
+    pub async fn get_active_lockout(&self, user_id: UserId, guild_id: GuildId)
+                                    -> Result<Option<Lockout>, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+        let now = Utc::now().naive_utc();
+
+        sqlx::query_as!(
+            Lockout,
+            "SELECT id as 'id!', user_id, until, attempts, channel_id
+             FROM lockout
+             WHERE user_id=? AND guild_id=? AND until > ?
+             ORDER BY until DESC LIMIT 1",
+            user_id, guild_id, now)
+            .fetch_optional(&self.database)
+            .await
+    }
+
+    pub async fn get_guild_lockouts(&self, guild_id: GuildId)
+                                    -> Result<Vec<Lockout>, sqlx::Error> {
+        let guild_id = guild_id.0 as i64;
+        let now = Utc::now().naive_utc();
+
+        sqlx::query_as!(
+            Lockout,
+            "SELECT id as 'id!', user_id, until, attempts, channel_id
+             FROM lockout
+             WHERE guild_id=? AND until > ?
+             ORDER BY until ASC",
+            guild_id, now)
+            .fetch_all(&self.database)
+            .await
+    }
+
+    pub async fn count_lockout_attempt(&self, lockout_id: i64) -> Result<i64, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE lockout SET attempts=attempts+1 WHERE id=? RETURNING attempts",
+            lockout_id
+            )
+            .fetch_one(&self.database)
+            .await?;
+        Ok(result.attempts)
+    }
+
+    // 既に記録があれば参加時刻はそのまま
+    pub async fn start_voice_session(&self, user_id: UserId, guild_id: GuildId, started_at: NaiveDateTime)
+                                     -> Result<VoiceSession, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query!(
+            "INSERT OR IGNORE INTO voice_session (user_id, guild_id, started_at) VALUES (?, ?, ?)",
+            user_id, guild_id, started_at
+            )
+            .execute(&self.database)
+            .await?;
+
+        sqlx::query_as!(
+            VoiceSession,
+            "SELECT user_id, started_at, reservation_id, seen_at
+             FROM voice_session WHERE user_id=? AND guild_id=?",
+            user_id, guild_id)
+            .fetch_one(&self.database)
+            .await
+    }
+
+    pub async fn get_voice_session(&self, user_id: UserId, guild_id: GuildId)
+                                   -> Result<Option<VoiceSession>, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query_as!(
+            VoiceSession,
+            "SELECT user_id, started_at, reservation_id, seen_at
+             FROM voice_session WHERE user_id=? AND guild_id=?",
+            user_id, guild_id)
+            .fetch_optional(&self.database)
+            .await
+    }
+
+    pub async fn get_guild_voice_sessions(&self, guild_id: GuildId)
+                                          -> Result<Vec<VoiceSession>, sqlx::Error> {
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query_as!(
+            VoiceSession,
+            "SELECT user_id, started_at, reservation_id, seen_at
+             FROM voice_session WHERE guild_id=?",
+            guild_id)
+            .fetch_all(&self.database)
+            .await
+    }
+
+    pub async fn update_voice_session_reservation(&self, user_id: UserId, guild_id: GuildId,
+                                                  reservation_id: Option<i64>)
+                                                  -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query!(
+            "UPDATE voice_session SET reservation_id=? WHERE user_id=? AND guild_id=?",
+            reservation_id, user_id, guild_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    // Botが動いている間、通話中の記録に確認した時刻を残す
+    pub async fn touch_voice_sessions(&self, seen_at: NaiveDateTime)
+                                      -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        sqlx::query!("UPDATE voice_session SET seen_at=?", seen_at)
+            .execute(&self.database)
+            .await
+    }
+
+    // 通話を抜けたら参加時刻と上限の切断予約を削除
+    pub async fn end_voice_session(&self, user_id: UserId, guild_id: GuildId)
+                                   -> Result<Option<VoiceSession>, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        let session = sqlx::query_as!(
+            VoiceSession,
+            "DELETE FROM voice_session WHERE user_id=? AND guild_id=?
+             RETURNING user_id, started_at, reservation_id, seen_at",
+            user_id, guild_id)
+            .fetch_optional(&self.database)
+            .await?;
+
+        if let Some(reservation_id) = session.as_ref().and_then(|s| s.reservation_id) {
+            sqlx::query!("DELETE FROM reservation WHERE id=?", reservation_id)
+                .execute(&self.database)
+                .await?;
+            self.job_changed.notify_one();
+        }
+        Ok(session)
+    }
+
+    pub async fn get_voice_budget(&self, user_id: UserId, guild_id: GuildId)
+                                  -> Result<Option<VoiceBudget>, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query_as!(
+            VoiceBudget,
+            "SELECT daily_minutes, used_seconds, day, reservation_id
+             FROM voice_budget WHERE user_id=? AND guild_id=?",
+            user_id, guild_id)
+            .fetch_optional(&self.database)
+            .await
+    }
+
+    // 上限を変えても今日の使用時間は引き継ぐ
+    pub async fn upsert_voice_budget(&self, user_id: UserId, guild_id: GuildId, daily_minutes: i64)
+                                     -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query!(
+            "INSERT INTO voice_budget (user_id, guild_id, daily_minutes) VALUES (?, ?, ?)
+             ON CONFLICT(user_id, guild_id) DO UPDATE SET daily_minutes=excluded.daily_minutes",
+            user_id, guild_id, daily_minutes
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    pub async fn update_voice_budget_usage(&self, user_id: UserId, guild_id: GuildId,
+                                           day: &str, used_seconds: i64)
+                                           -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query!(
+            "UPDATE voice_budget SET day=?, used_seconds=? WHERE user_id=? AND guild_id=?",
+            day, used_seconds, user_id, guild_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    pub async fn update_voice_budget_reservation(&self, user_id: UserId, guild_id: GuildId,
+                                                 reservation_id: Option<i64>)
+                                                 -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query!(
+            "UPDATE voice_budget SET reservation_id=? WHERE user_id=? AND guild_id=?",
+            reservation_id, user_id, guild_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    // 切断予約も合わせて削除
+    pub async fn delete_voice_budget(&self, user_id: UserId, guild_id: GuildId)
+                                     -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+        let policy = Policy::Budget;
+
+        sqlx::query!(
+            "DELETE FROM reservation WHERE user_id=? AND guild_id=? AND policy=?",
+            user_id, guild_id, policy
+            )
+            .execute(&self.database)
+            .await?;
+        self.job_changed.notify_one();
+
+        sqlx::query!(
+            "DELETE FROM voice_budget WHERE user_id=? AND guild_id=?",
+            user_id, guild_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    // 既にミュートしていれば段階を追加する
+    pub async fn upsert_muted_member(&self, user_id: UserId, guild_id: GuildId, reservation_id: i64,
+                                     mute: bool, deaf: bool)
+                                     -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query!(
+            "INSERT INTO muted_member (user_id, guild_id, reservation_id, mute, deaf) VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT(user_id, guild_id) DO UPDATE
+             SET reservation_id=excluded.reservation_id,
+                 mute=mute OR excluded.mute, deaf=deaf OR excluded.deaf",
+            user_id, guild_id, reservation_id, mute, deaf
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    // 予約の段階が終わったミュートを解除対象にする
+    pub async fn release_reservation_mutes(&self, reservation_id: i64)
+                                           -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        sqlx::query!(
+            "UPDATE muted_member SET reservation_id=NULL WHERE reservation_id=?",
+            reservation_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    pub async fn release_member_mute(&self, user_id: UserId, guild_id: GuildId)
+                                     -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query!(
+            "UPDATE muted_member SET reservation_id=NULL WHERE user_id=? AND guild_id=?",
+            user_id, guild_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    pub async fn get_released_mutes(&self, guild_id: GuildId)
+                                    -> Result<Vec<MutedMember>, sqlx::Error> {
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query_as!(
+            MutedMember,
+            "SELECT user_id, mute, deaf
+             FROM muted_member WHERE guild_id=? AND reservation_id IS NULL",
+            guild_id)
+            .fetch_all(&self.database)
+            .await
+    }
+
+    pub async fn delete_muted_member(&self, user_id: UserId, guild_id: GuildId)
+                                     -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query!(
+            "DELETE FROM muted_member WHERE user_id=? AND guild_id=?",
+            user_id, guild_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    // 期限切れの入力は新しい入力を保存するときに消す
+    pub async fn insert_snipe_draft(&self, user_id: UserId, guild_id: GuildId, request: &SnipeRequest)
+                                    -> Result<i64, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+        let guild_id = guild_id.0 as i64;
+        let now = Utc::now().naive_utc();
+        let expired = now - Duration::minutes(SnipeDraft::TTL_MINUTES);
+        let recurrence = request.recurrence.as_ref().map(|r| r.to_string());
+        let warnings = request.lead_times.as_ref().map(|l| l.to_string());
+        let action = request.action.map(|a| a.kind());
+        let action_channel_id = request.action.and_then(|a| a.channel_id());
+        let targets = request.targets.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(",");
+
+        sqlx::query!("DELETE FROM snipe_draft WHERE created_at < ?", expired)
+            .execute(&self.database)
+            .await?;
+
+        let result = sqlx::query!(
+            "INSERT INTO snipe_draft
+             (user_id, guild_id, time, recurrence, warnings, lockout, action, action_channel_id,
+              escalate, reason, targets, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            user_id, guild_id, request.time, recurrence, warnings, request.lockout,
+            action, action_channel_id, request.escalate, request.reason, targets, now
+            )
+            .execute(&self.database)
+            .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn get_snipe_draft(&self, draft_id: i64) -> Result<Option<SnipeDraft>, sqlx::Error> {
+        sqlx::query_as!(
+            SnipeDraft,
+            "SELECT id, user_id, guild_id, time, recurrence, warnings, lockout,
+                    action as 'action: ActionKind', action_channel_id, escalate, reason, targets, created_at
+             FROM snipe_draft WHERE id=?",
+            draft_id)
+            .fetch_optional(&self.database)
+            .await
+    }
+
+    // 削除できた場合だけtrue（ボタンが続けて押されても一度だけ予約する）
+    pub async fn delete_snipe_draft(&self, draft_id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!("DELETE FROM snipe_draft WHERE id=?", draft_id)
+            .execute(&self.database)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn get_guild_setting(&self, guild_id: GuildId)
                                    -> Result<GuildSetting, sqlx::Error> {
         let guild_id = guild_id.0 as i64;
 
         sqlx::query_as!(
             GuildSetting,
-            "SELECT guild_id, utc_offset as 'utc_offset!: i32' FROM setting WHERE guild_id=?",
+            "SELECT guild_id, timezone, warnings, snooze_count, snooze_minutes,
+                    quiet_start, quiet_end, quiet_role_id, quiet_reservation_id, max_session_minutes,
+                    action as 'action: ActionKind', action_channel_id, max_horizon_days
+             FROM setting WHERE guild_id=?",
             guild_id)
             .fetch_one(&self.database)
             .await
     }
 
-    pub async fn insert_guild_setting(&self, guild_id: GuildId, utc_offset: u64)
+    // ユーザー設定のタイムゾーンを優先し、未設定ならギルドの設定を使う
+    pub async fn get_timezone(&self, user_id: UserId, guild_id: GuildId)
+                              -> Result<Tz, sqlx::Error> {
+        match self.get_user_setting(user_id).await? {
+            Some(user_setting) => Ok(user_setting.tz()),
+            None => Ok(self.get_guild_setting(guild_id).await?.tz())
+        }
+    }
+
+    pub async fn insert_guild_setting(&self, guild_id: GuildId, timezone: &str)
                                 -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
         let guild_id = guild_id.0 as i64;
-        let utc_offset = utc_offset as i64;
 
         sqlx::query!(
-            "INSERT INTO setting (guild_id, utc_offset) VALUES (?, ?)",
-            guild_id, utc_offset
+            "INSERT INTO setting (guild_id, timezone) VALUES (?, ?)",
+            guild_id, timezone
             )
             .execute(&self.database)
             .await
@@ -129,13 +867,97 @@ impl SqliteDatabase {
             .await
     }
 
-    pub async fn update_guild_setting(&self, guild_id: GuildId, utc_offset: i64)
+    pub async fn update_guild_setting(&self, guild_id: GuildId, timezone: &str)
+                                -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query!(
+            "UPDATE setting SET timezone=? WHERE guild_id=?",
+            timezone, guild_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    pub async fn update_guild_warnings(&self, guild_id: GuildId, lead_times: &LeadTimes)
+                                -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let guild_id = guild_id.0 as i64;
+        let warnings = lead_times.to_string();
+
+        sqlx::query!(
+            "UPDATE setting SET warnings=? WHERE guild_id=?",
+            warnings, guild_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    pub async fn update_guild_snooze(&self, guild_id: GuildId, count: i64, minutes: i64)
                                 -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
         let guild_id = guild_id.0 as i64;
 
         sqlx::query!(
-            "UPDATE setting SET utc_offset=? WHERE guild_id=?",
-            utc_offset, guild_id
+            "UPDATE setting SET snooze_count=?, snooze_minutes=? WHERE guild_id=?",
+            count, minutes, guild_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    pub async fn update_guild_quiet_hours(&self,
+                        guild_id: GuildId,
+                        quiet_hours: Option<&QuietHours>,
+                        role_id: Option<RoleId>,
+                        reservation_id: Option<i64>)
+                        -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let guild_id = guild_id.0 as i64;
+        let start = quiet_hours.map(|q| q.start.format("%H:%M").to_string());
+        let end = quiet_hours.map(|q| q.end.format("%H:%M").to_string());
+        let role_id = role_id.map(|r| r.0 as i64);
+
+        sqlx::query!(
+            "UPDATE setting
+             SET quiet_start=?, quiet_end=?, quiet_role_id=?, quiet_reservation_id=?
+             WHERE guild_id=?",
+            start, end, role_id, reservation_id, guild_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    pub async fn update_guild_max_session(&self, guild_id: GuildId, minutes: Option<i64>)
+                                -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query!(
+            "UPDATE setting SET max_session_minutes=? WHERE guild_id=?",
+            minutes, guild_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    pub async fn update_guild_max_horizon(&self, guild_id: GuildId, days: i64)
+                                -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let guild_id = guild_id.0 as i64;
+
+        sqlx::query!(
+            "UPDATE setting SET max_horizon_days=? WHERE guild_id=?",
+            days, guild_id
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    pub async fn update_guild_action(&self, guild_id: GuildId, action: Action)
+                                -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let guild_id = guild_id.0 as i64;
+        let kind = action.kind();
+        let channel_id = action.channel_id();
+
+        sqlx::query!(
+            "UPDATE setting SET action=?, action_channel_id=? WHERE guild_id=?",
+            kind, channel_id, guild_id
             )
             .execute(&self.database)
             .await
@@ -144,9 +966,317 @@ impl SqliteDatabase {
     pub async fn get_settings(&self) -> Result<Vec<GuildSetting>, sqlx::Error> {
         sqlx::query_as!(
             GuildSetting,
-            r#"SELECT guild_id, utc_offset as "utc_offset!: i32" FROM setting"#
+            "SELECT guild_id, timezone, warnings, snooze_count, snooze_minutes,
+                    quiet_start, quiet_end, quiet_role_id, quiet_reservation_id, max_session_minutes,
+                    action as 'action: ActionKind', action_channel_id, max_horizon_days
+             FROM setting"
             )
             .fetch_all(&self.database)
             .await
     }
+
+    pub async fn get_user_setting(&self, user_id: UserId)
+                                  -> Result<Option<UserSetting>, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+
+        sqlx::query_as!(
+            UserSetting,
+            "SELECT timezone FROM user_setting WHERE user_id=?",
+            user_id)
+            .fetch_optional(&self.database)
+            .await
+    }
+
+    pub async fn upsert_user_setting(&self, user_id: UserId, timezone: &str)
+                                -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+
+        sqlx::query!(
+            "INSERT INTO user_setting (user_id, timezone) VALUES (?, ?)
+             ON CONFLICT(user_id) DO UPDATE SET timezone=excluded.timezone",
+            user_id, timezone
+            )
+            .execute(&self.database)
+            .await
+    }
+
+    pub async fn delete_user_setting(&self, user_id: UserId)
+                                -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
+        let user_id = user_id.0 as i64;
+
+        sqlx::query!(
+            "DELETE FROM user_setting WHERE user_id=?",
+            user_id
+            )
+            .execute(&self.database)
+            .await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // テストごとに別ファイルのデータベースを作る（:memory:は接続ごとに別のデータベースになる）
+    pub(crate) async fn test_database(name: &str) -> SqliteDatabase {
+        let path = std::env::temp_dir().join(format!("vc-timer-{}-{}.sqlite", std::process::id(), name));
+        let path = path.to_str().unwrap();
+        // 以前の実行でプロセスIDが重なった場合のWALファイルも消す
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+        let database = SqliteDatabase::new(path).await;
+        database.insert_guild_setting(GUILD, "UTC").await.unwrap();
+        database
+    }
+
+    pub(crate) const GUILD: GuildId = GuildId(1);
+    pub(crate) const USER: UserId = UserId(2);
+
+    // 期限を過ぎた切断予約を登録する
+    pub(crate) async fn due_reservation(database: &SqliteDatabase, target: Target, options: &ReservationOptions) -> i64 {
+        let id = database.insert_reservation(USER, GUILD, &target, options).await.unwrap();
+        let at = Utc::now().naive_utc() - Duration::minutes(1);
+        database.insert_disconnect_jobs(id, at, USER, GUILD).await.unwrap();
+        id
+    }
+
+    // 通知を待たずに済んだらtrue（通知済みなら待ち受けはすぐに終わる）
+    async fn notified(database: &SqliteDatabase) -> bool {
+        let wait = std::time::Duration::from_millis(100);
+        tokio::time::timeout(wait, database.job_changed().notified()).await.is_ok()
+    }
+
+    #[tokio::test]
+    async fn job_changes_wake_the_runner() {
+        let database = test_database("job_changed").await;
+        assert_eq!(database.next_job_time().await.unwrap(), None);
+        assert!(!notified(&database).await);
+
+        let id = database.insert_reservation(USER, GUILD, &Target::User(USER), &ReservationOptions::default())
+            .await.unwrap();
+        let at = Utc::now().naive_utc() + Duration::hours(1);
+        database.insert_disconnect_jobs(id, at, USER, GUILD).await.unwrap();
+        assert!(notified(&database).await);
+        assert!(database.next_job_time().await.unwrap().is_some_and(|t| t <= at));
+
+        // 早めると次に待つ時刻も早まる
+        let earlier = at - Duration::minutes(30);
+        database.reschedule_disconnect_jobs(id, earlier, USER, GUILD).await.unwrap();
+        assert!(notified(&database).await);
+        assert_eq!(database.get_job_time(id, Disconnect::NAME).await.unwrap(), Some(earlier));
+        assert!(database.next_job_time().await.unwrap().is_some_and(|t| t <= earlier));
+
+        database.delete_reservation(id, USER, GUILD).await.unwrap();
+        assert!(notified(&database).await);
+        assert_eq!(database.next_job_time().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn snooze_stops_at_guild_limits() {
+        let database = test_database("snooze_limits").await;
+        database.update_guild_snooze(GUILD, 3, 20).await.unwrap();
+        let setting = database.get_guild_setting(GUILD).await.unwrap();
+        let id = database.insert_reservation(USER, GUILD, &Target::User(USER), &ReservationOptions::default())
+            .await.unwrap();
+
+        // 合計時間の上限を超える延長はできない
+        assert!(database.add_snooze(id, 15, &setting).await.unwrap());
+        assert!(!database.add_snooze(id, 15, &setting).await.unwrap());
+        assert!(database.add_snooze(id, 5, &setting).await.unwrap());
+        let reservation = database.get_reservation(id).await.unwrap().unwrap();
+        assert_eq!((reservation.snoozed_count, reservation.snoozed_minutes), (2, 20));
+        assert_eq!(reservation.snooze_left(&setting), (1, 0));
+
+        // 回数の上限
+        database.update_guild_snooze(GUILD, 2, 60).await.unwrap();
+        let setting = database.get_guild_setting(GUILD).await.unwrap();
+        assert!(!database.add_snooze(id, 5, &setting).await.unwrap());
+
+        // 繰り返し予約の次回分では延長回数を戻す
+        let mut tx = database.database.begin().await.unwrap();
+        SqliteDatabase::reset_snooze(&mut tx, id).await.unwrap();
+        tx.commit().await.unwrap();
+        assert!(database.add_snooze(id, 5, &setting).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn target_round_trip() {
+        let database = test_database("target_round_trip").await;
+        // ユーザー指定の予約はそのユーザーのものとして登録される（snipe::add_job）
+        let targets = [Target::User(USER), Target::Channel(ChannelId(3)), Target::Server, Target::Role(RoleId(4))];
+        for target in targets {
+            let id = database.insert_reservation(USER, GUILD, &target, &ReservationOptions::default())
+                .await.unwrap();
+            let reservation = database.get_reservation(id).await.unwrap().unwrap();
+            assert_eq!(reservation.target(), target);
+        }
+    }
+
+    #[tokio::test]
+    async fn escalation_stages_share_the_deadline() {
+        let database = test_database("escalation").await;
+        let options = ReservationOptions { escalate: true, ..Default::default() };
+        let id = database.insert_reservation(USER, GUILD, &Target::User(USER), &options).await.unwrap();
+        let at = Utc::now().naive_utc() + Duration::hours(1);
+        database.insert_disconnect_jobs(id, at, USER, GUILD).await.unwrap();
+
+        for (name, minutes) in ESCALATION {
+            assert_eq!(database.get_job_time(id, name).await.unwrap(), Some(at + Duration::minutes(minutes)));
+        }
+        // 期限は最初の段階の時刻（切断のジョブから求める）
+        assert_eq!(database.get_deadline(id).await.unwrap(), Some(at));
+        assert_eq!(escalation_minutes("Kick"), 0);
+    }
+
+    #[tokio::test]
+    async fn escalation_mutes_are_released_by_reservation() {
+        let database = test_database("escalation_mutes").await;
+        let id = database.insert_reservation(USER, GUILD, &Target::Server, &ReservationOptions::default())
+            .await.unwrap();
+        database.upsert_muted_member(USER, GUILD, id, true, false).await.unwrap();
+        database.upsert_muted_member(USER, GUILD, id, false, true).await.unwrap();
+        database.upsert_muted_member(UserId(5), GUILD, id, true, false).await.unwrap();
+        assert!(database.get_released_mutes(GUILD).await.unwrap().is_empty());
+
+        database.release_reservation_mutes(id).await.unwrap();
+        let mut released = database.get_released_mutes(GUILD).await.unwrap();
+        released.sort_by_key(|m| m.user_id);
+        let released: Vec<_> = released.iter().map(|m| (m.userid(), m.mute, m.deaf)).collect();
+        assert_eq!(released, vec![(USER, true, true), (UserId(5), true, false)]);
+
+        database.delete_muted_member(USER, GUILD).await.unwrap();
+        assert_eq!(database.get_released_mutes(GUILD).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn snipe_draft_round_trip() {
+        let database = test_database("snipe_draft").await;
+        let request = SnipeRequest {
+            time: "23:00".to_string(),
+            recurrence: Some(crate::schedule::Recurrence::Daily),
+            lead_times: Some(LeadTimes::parse("10m,1m").unwrap()),
+            lockout: Some("30m".to_string()),
+            action: Some(Action::Move(ChannelId(3))),
+            escalate: true,
+            reason: Some("就寝".to_string()),
+            targets: vec![Target::User(USER), Target::Role(RoleId(4))],
+        };
+        let id = database.insert_snipe_draft(USER, GUILD, &request).await.unwrap();
+
+        // 再起動後にボタンが押されても同じ入力で予約できる
+        let draft = database.get_snipe_draft(id).await.unwrap().unwrap();
+        assert_eq!((draft.userid(), draft.guildid()), (USER, GUILD));
+        assert!(!draft.is_expired(Utc::now().naive_utc()));
+        assert!(draft.is_expired(Utc::now().naive_utc() + Duration::minutes(SnipeDraft::TTL_MINUTES + 1)));
+        let restored = draft.request();
+        assert_eq!(restored.time, request.time);
+        assert_eq!(restored.recurrence, request.recurrence);
+        assert_eq!(restored.lead_times, request.lead_times);
+        assert_eq!(restored.lockout, request.lockout);
+        assert_eq!(restored.action, request.action);
+        assert_eq!((restored.escalate, restored.reason), (request.escalate, request.reason));
+        assert_eq!(restored.targets, request.targets);
+
+        // ボタンが続けて押されても予約は一度だけ
+        assert!(database.delete_snipe_draft(id).await.unwrap());
+        assert!(!database.delete_snipe_draft(id).await.unwrap());
+        assert!(database.get_snipe_draft(id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn popped_reservation_keeps_its_target_until_finished() {
+        let database = test_database("popped_target").await;
+        let channel = Target::Channel(ChannelId(3));
+        let role = Target::Role(RoleId(4));
+        let channel_id = due_reservation(&database, channel, &ReservationOptions::default()).await;
+        let role_id = due_reservation(&database, role, &ReservationOptions::default()).await;
+
+        let (tx, jobs) = database.pop_executables().await.unwrap();
+        tx.commit().await.unwrap();
+        assert!(jobs.iter().any(|j| j.reservation_id == channel_id));
+        assert!(jobs.iter().any(|j| j.reservation_id == role_id));
+
+        // 実行時には作成者ではなく予約した対象に展開される
+        let reservation = database.get_reservation(channel_id).await.unwrap().unwrap();
+        assert_eq!(reservation.target(), channel);
+        let reservation = database.get_reservation(role_id).await.unwrap().unwrap();
+        assert_eq!(reservation.target(), role);
+
+        database.delete_finished_reservation(channel_id).await.unwrap();
+        assert!(database.get_reservation(channel_id).await.unwrap().is_none());
+        database.delete_finished_reservations().await.unwrap();
+        assert!(database.get_reservation(role_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn lockout_blocks_rejoin_and_keeps_reservation() {
+        let database = test_database("lockout_rejoin").await;
+        let options = ReservationOptions { lockout: Some(Duration::minutes(30)), ..Default::default() };
+        let id = due_reservation(&database, Target::User(USER), &options).await;
+
+        // 切断時と同じく、取り出した後の予約に対してロックアウトを登録する
+        let (tx, jobs) = database.pop_executables().await.unwrap();
+        tx.commit().await.unwrap();
+        let job = jobs.iter().find(|j| j.is(Disconnect::NAME)).unwrap();
+        let until = job.naive_utc + Duration::minutes(30);
+        database.insert_lockout(id, USER, GUILD, until, None).await.unwrap();
+
+        // 再参加しようとするとロックアウト中として扱われる
+        let lockout = database.get_active_lockout(USER, GUILD).await.unwrap().unwrap();
+        assert_eq!(lockout.until, until);
+        assert!(database.get_active_lockout(UserId(5), GUILD).await.unwrap().is_none());
+
+        // ロックアウト中は予約を残し、期限が切れていないので何も削除されない
+        database.delete_finished_reservation(id).await.unwrap();
+        assert!(database.get_reservation(id).await.unwrap().is_some());
+        assert!(database.expire_lockouts().await.unwrap().is_empty());
+
+        // 実行中に取り消された予約にはロックアウトを登録しない
+        database.delete_reservation(id, USER, GUILD).await.unwrap();
+        let result = database.insert_lockout(id, USER, GUILD, until, None).await.unwrap();
+        assert_eq!(result.rows_affected(), 0);
+    }
+
+    #[tokio::test]
+    async fn active_lockout_is_the_latest_unexpired() {
+        let database = test_database("active_lockout").await;
+        let id = database.insert_reservation(USER, GUILD, &Target::User(USER), &ReservationOptions::default())
+            .await.unwrap();
+        let now = Utc::now().naive_utc();
+        database.insert_lockout(id, USER, GUILD, now - Duration::minutes(1), None).await.unwrap();
+        assert!(database.get_active_lockout(USER, GUILD).await.unwrap().is_none());
+
+        let later = now + Duration::hours(2);
+        database.insert_lockout(id, USER, GUILD, now + Duration::hours(1), None).await.unwrap();
+        database.insert_lockout(id, USER, GUILD, later, Some(ChannelId(9))).await.unwrap();
+        let lockout = database.get_active_lockout(USER, GUILD).await.unwrap().unwrap();
+        assert_eq!((lockout.until, lockout.channel_id), (later, Some(9)));
+        assert!(database.get_active_lockout(USER, GuildId(8)).await.unwrap().is_none());
+
+        // 期限切れの分だけ削除し、その予約を返す
+        assert_eq!(database.expire_lockouts().await.unwrap(), vec![id]);
+        assert!(database.get_active_lockout(USER, GUILD).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn recurrence_is_scheduled_with_the_pop() {
+        let database = test_database("recurrence_pop").await;
+        let schedule = crate::schedule::CronSchedule::parse("0 9 * * *", Tz::UTC).unwrap();
+        let options = ReservationOptions { schedule: Some(Schedule::Cron(Box::new(schedule))), ..Default::default() };
+        let id = due_reservation(&database, Target::User(USER), &options).await;
+        let actions = crate::actions::ActionRegistry::default();
+
+        // 次回分を登録する前に失敗したら、取り出したジョブごと元に戻る
+        let (tx, jobs) = database.pop_executables().await.unwrap();
+        assert!(!jobs.is_empty());
+        drop(tx);
+        assert!(database.next_job_time().await.unwrap().is_some_and(|t| t <= Utc::now().naive_utc()));
+
+        let (mut tx, jobs) = database.pop_executables().await.unwrap();
+        crate::runner::schedule_recurrences(&database, &mut tx, &actions, &jobs).await.unwrap();
+        tx.commit().await.unwrap();
+        let next = database.get_job_time(id, Disconnect::NAME).await.unwrap().unwrap();
+        assert!(next > Utc::now().naive_utc());
+    }
 }
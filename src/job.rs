@@ -1,41 +1,567 @@
 use serenity::{
-    client::Context,
+    CacheAndHttp,
     builder::CreateMessage,
     model::{
-        guild::Member,
+        guild::{Guild, Member},
         channel::Message,
-        id::{GuildId, UserId}
+        mention::Mention,
+        id::{ChannelId, GuildId, RoleId, UserId}
     }
 };
-use chrono::NaiveDateTime;
-use sqlx;
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use chrono_tz::Tz;
+use crate::actions;
+use crate::schedule::{Recurrence, Repeat, CronSchedule, Schedule, LeadTimes, QuietHours};
 use std::hash::Hash;
+use std::fmt;
 
-#[derive(Debug, Hash, PartialEq, sqlx::Type)]
-pub enum EventType {
+// 段階的な切断の各段階（処理の名前）と期限からの時間（分）
+pub const ESCALATION: [(&str, i64); 3] = [
+    (actions::mute::Mute::NAME, 0),
+    (actions::mute::Deafen::NAME, 2),
+    (actions::disconnect::Disconnect::NAME, 5),
+];
+
+// 段階的な切断で、期限から何分後にその段階を行うか
+pub fn escalation_minutes(event_type: &str) -> i64 {
+    ESCALATION.iter()
+        .find(|(e, _)| *e == event_type)
+        .map_or(0, |&(_, minutes)| minutes)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, sqlx::Type)]
+pub enum TargetKind {
+    User,
+    Channel,
+    Server,
+    Role,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, sqlx::Type)]
+pub enum ActionKind {
     Disconnect,
-    Notification3Min,
+    Move,
+    Afk,
+}
+
+// 切断時の動作
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Disconnect,
+    Move(ChannelId),
+    Afk,
+}
+
+impl Action {
+    pub fn from_parts(kind: ActionKind, channel_id: Option<i64>) -> Option<Self> {
+        match kind {
+            ActionKind::Disconnect => Some(Self::Disconnect),
+            ActionKind::Move => Some(Self::Move(ChannelId::from(channel_id? as u64))),
+            ActionKind::Afk => Some(Self::Afk),
+        }
+    }
+
+    pub fn kind(&self) -> ActionKind {
+        match self {
+            Self::Disconnect => ActionKind::Disconnect,
+            Self::Move(_) => ActionKind::Move,
+            Self::Afk => ActionKind::Afk,
+        }
+    }
+
+    pub fn channel_id(&self) -> Option<i64> {
+        match self {
+            Self::Move(c) => Some(c.0 as i64),
+            _ => None,
+        }
+    }
+
+    // 「〜します」「〜しました」に続ける表示
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Disconnect => "通話を強制切断".to_string(),
+            Self::Move(c) => format!("{}へ移動", Mention::from(*c)),
+            Self::Afk => "AFKチャンネルへ移動".to_string(),
+        }
+    }
+
+    // ギルドで実際に行う動作の表示（AFKチャンネル未設定のギルドでは切断する）
+    pub fn describe_in(&self, guild: &Guild) -> String {
+        match self.destination(guild) {
+            Some(c) => format!("{}へ移動", Mention::from(c)),
+            None => Self::Disconnect.describe()
+        }
+    }
+
+    // 移動先のチャンネル（Noneなら切断。AFKチャンネル未設定のギルドも切断する）
+    pub fn destination(&self, guild: &Guild) -> Option<ChannelId> {
+        match self {
+            Self::Disconnect => None,
+            Self::Move(c) => Some(*c),
+            Self::Afk => guild.afk_channel_id,
+        }
+    }
+}
+
+// 切断対象（Channel/Server/Roleは切断時点で通話中のユーザーに展開する）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Target {
+    User(UserId),
+    Channel(ChannelId),
+    Server,
+    Role(RoleId),
+}
+
+impl Target {
+    pub fn kind(&self) -> TargetKind {
+        match self {
+            Self::User(_) => TargetKind::User,
+            Self::Channel(_) => TargetKind::Channel,
+            Self::Server => TargetKind::Server,
+            Self::Role(_) => TargetKind::Role,
+        }
+    }
+
+    pub fn id(&self) -> Option<i64> {
+        match self {
+            Self::User(u) => Some(u.0 as i64),
+            Self::Channel(c) => Some(c.0 as i64),
+            Self::Server => None,
+            Self::Role(r) => Some(r.0 as i64),
+        }
+    }
+
+    pub fn parse(input: &str) -> Option<Self> {
+        let (kind, id) = match input.split_once(':') {
+            Some((kind, id)) => (kind, Some(id.parse::<u64>().ok()?)),
+            None => (input, None)
+        };
+        match (kind, id) {
+            ("User", Some(u)) => Some(Self::User(UserId::from(u))),
+            ("Channel", Some(c)) => Some(Self::Channel(ChannelId::from(c))),
+            ("Server", None) => Some(Self::Server),
+            ("Role", Some(r)) => Some(Self::Role(RoleId::from(r))),
+            _ => None
+        }
+    }
+
+    pub fn mention(&self) -> String {
+        match self {
+            Self::User(u) => Mention::from(*u).to_string(),
+            Self::Channel(c) => format!("{}の全員", Mention::from(*c)),
+            Self::Server => "サーバー全体の通話".to_string(),
+            Self::Role(r) => format!("{}のメンバー", Mention::from(*r)),
+        }
+    }
+
+    // ギルドのボイス状態から、現在通話中の対象ユーザーを集める
+    // ロールは予約時ではなくこの時点のメンバーで判定する
+    pub async fn resolve(&self, ctx: &CacheAndHttp, guild: &Guild) -> Vec<UserId> {
+        let in_voice = self.in_voice(guild.voice_states
+            .values()
+            .map(|voice_state| (voice_state.user_id, voice_state.channel_id)));
+
+        let Self::Role(role_id) = self else {
+            return in_voice;
+        };
+
+        let mut user_ids = Vec::new();
+        for user_id in in_voice {
+            match guild.member(ctx, user_id).await {
+                Ok(member) if member.roles.contains(role_id) => user_ids.push(user_id),
+                Ok(_) => {},
+                Err(why) => println!("cannot get member {}: {}", user_id, why)
+            }
+        }
+        user_ids
+    }
+
+    // 通話中のユーザーのうち対象に当てはまるもの（ロールはこの後メンバーの情報で絞り込む）
+    fn in_voice(&self, voice_states: impl Iterator<Item = (UserId, Option<ChannelId>)>) -> Vec<UserId> {
+        voice_states
+            .filter(|&(user_id, channel_id)| match self {
+                Self::User(u) => user_id == *u && channel_id.is_some(),
+                Self::Channel(c) => channel_id == Some(*c),
+                Self::Server | Self::Role(_) => channel_id.is_some(),
+            })
+            .map(|(user_id, _)| user_id)
+            .collect()
+    }
+
+    // 通話中でなくても対象になるユーザー（ロールはサーバーの全メンバー、チャンネル/サーバー全体は通話中のみ）
+    pub async fn members(&self, ctx: &CacheAndHttp, guild: &Guild) -> Vec<UserId> {
+        match self {
+            Self::User(u) => vec![*u],
+            Self::Role(r) => match role_members(ctx, guild.id, *r).await {
+                Ok(user_ids) => user_ids,
+                Err(why) => {
+                    // 取得できなければキャッシュにあるメンバーだけで代用する
+                    println!("fetch members of role {}: {}", r, why);
+                    guild.members
+                        .values()
+                        .filter(|member| member.roles.contains(r))
+                        .map(|member| member.user.id)
+                        .collect()
+                }
+            },
+            Self::Channel(_) | Self::Server => self.resolve(ctx, guild).await,
+        }
+    }
+}
+
+// ロールを持つメンバーをAPIから1000人ずつ取得する
+// （GUILD_MEMBERSインテントを要求していないのでキャッシュにはメンバーが揃わない。
+//   この取得にはDeveloper PortalでServer Members Intentを有効にする必要がある）
+async fn role_members(ctx: &CacheAndHttp, guild_id: GuildId, role_id: RoleId) -> serenity::Result<Vec<UserId>> {
+    let mut user_ids = Vec::new();
+    let mut after = None;
+    loop {
+        let members = guild_id.members(&ctx.http, Some(1000), after).await?;
+        after = members.last().map(|m| m.user.id);
+        user_ids.extend(members.iter()
+            .filter(|member| member.roles.contains(&role_id))
+            .map(|member| member.user.id));
+        if members.len() < 1000 {
+            return Ok(user_ids);
+        }
+    }
+}
+
+// ギルドの設定によって作られる予約の種類
+#[derive(Debug, Clone, Copy, PartialEq, sqlx::Type)]
+pub enum Policy {
+    QuietHours,
+    Session,
+    Budget,
+}
+
+impl Policy {
+    pub fn describe(&self) -> &str {
+        match self {
+            Self::QuietHours => "静粛時間",
+            Self::Session => "連続通話の上限",
+            Self::Budget => "1日の通話時間",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct GuildSetting {
     pub guild_id: i64,
-    pub utc_offset: i32
+    pub timezone: String,
+    pub warnings: String,
+    pub snooze_count: i64,
+    pub snooze_minutes: i64,
+    pub quiet_start: Option<String>,
+    pub quiet_end: Option<String>,
+    pub quiet_role_id: Option<i64>,
+    pub quiet_reservation_id: Option<i64>,
+    pub max_session_minutes: Option<i64>,
+    pub action: ActionKind,
+    pub action_channel_id: Option<i64>,
+    pub max_horizon_days: i64
+}
+
+impl GuildSetting {
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+
+    pub fn lead_times(&self) -> LeadTimes {
+        LeadTimes::parse(&self.warnings).unwrap_or_default()
+    }
+
+    pub fn quiet_hours(&self) -> Option<QuietHours> {
+        let start = NaiveTime::parse_from_str(self.quiet_start.as_deref()?, "%H:%M").ok()?;
+        let end = NaiveTime::parse_from_str(self.quiet_end.as_deref()?, "%H:%M").ok()?;
+        QuietHours::new(start, end).ok()
+    }
+
+    pub fn quiet_role(&self) -> Option<RoleId> {
+        self.quiet_role_id.map(|r| RoleId::from(r as u64))
+    }
+
+    pub fn max_session(&self) -> Option<Duration> {
+        self.max_session_minutes.map(Duration::minutes)
+    }
+
+    pub fn action(&self) -> Action {
+        Action::from_parts(self.action, self.action_channel_id).unwrap_or(Action::Disconnect)
+    }
+
+    // 相対指定で予約できる最大の時間
+    pub fn max_horizon(&self) -> Duration {
+        Duration::days(self.max_horizon_days)
+    }
+}
+
+#[derive(Debug)]
+pub struct UserSetting {
+    pub timezone: String
+}
+
+impl UserSetting {
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+}
+
+impl fmt::Display for Target {
+    // Target::parseで読み戻せる形式で出力
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.id() {
+            Some(id) => write!(f, "{:?}:{}", self.kind(), id),
+            None => write!(f, "{:?}", self.kind()),
+        }
+    }
+}
+
+// /snipeの入力（指定方法が決まるまではsnipe_draftに保存する）
+#[derive(Debug, Clone)]
+pub struct SnipeRequest {
+    pub time: String,
+    pub recurrence: Option<Recurrence>,
+    pub lead_times: Option<LeadTimes>,
+    // 切断時刻が決まってから解釈する
+    pub lockout: Option<String>,
+    pub action: Option<Action>,
+    pub escalate: bool,
+    pub reason: Option<String>,
+    pub targets: Vec<Target>,
+}
+
+#[derive(Debug)]
+pub struct SnipeDraft {
+    pub id: i64,
+    pub user_id: i64,
+    pub guild_id: i64,
+    pub time: String,
+    pub recurrence: Option<String>,
+    pub warnings: Option<String>,
+    pub lockout: Option<String>,
+    pub action: Option<ActionKind>,
+    pub action_channel_id: Option<i64>,
+    pub escalate: bool,
+    pub reason: Option<String>,
+    pub targets: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl SnipeDraft {
+    // 指定方法のボタンを受け付ける時間
+    pub const TTL_MINUTES: i64 = 10;
+
+    pub fn userid(&self) -> UserId {
+        UserId::from(self.user_id as u64)
+    }
+
+    pub fn guildid(&self) -> GuildId {
+        GuildId::from(self.guild_id as u64)
+    }
+
+    pub fn is_expired(&self, now: NaiveDateTime) -> bool {
+        now - self.created_at > Duration::minutes(Self::TTL_MINUTES)
+    }
+
+    pub fn request(&self) -> SnipeRequest {
+        SnipeRequest {
+            time: self.time.clone(),
+            recurrence: self.recurrence.as_deref().and_then(|r| Recurrence::parse(r).ok()),
+            lead_times: self.warnings.as_deref().and_then(|w| LeadTimes::parse(w).ok()),
+            lockout: self.lockout.clone(),
+            action: self.action.and_then(|kind| Action::from_parts(kind, self.action_channel_id)),
+            escalate: self.escalate,
+            reason: self.reason.clone(),
+            targets: self.targets.split(',').filter_map(Target::parse).collect(),
+        }
+    }
+}
+
+// 予約ごとの指定（未指定の項目はギルドの設定に従う）
+#[derive(Debug, Default)]
+pub struct ReservationOptions {
+    pub schedule: Option<Schedule>,
+    pub lead_times: Option<LeadTimes>,
+    pub lockout: Option<Duration>,
+    pub policy: Option<Policy>,
+    pub action: Option<Action>,
+    pub escalate: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Reservation {
+    pub id: i64,
+    pub user_id: i64,
+    pub guild_id: i64,
+    pub recurrence: Option<String>,
+    pub local_time: Option<String>,
+    pub timezone: Option<String>,
+    pub cron: Option<String>,
+    pub warnings: Option<String>,
+    pub snoozed_count: i64,
+    pub snoozed_minutes: i64,
+    pub target_kind: TargetKind,
+    pub target_id: Option<i64>,
+    pub lockout_minutes: Option<i64>,
+    pub policy: Option<Policy>,
+    pub action: Option<ActionKind>,
+    pub action_channel_id: Option<i64>,
+    pub escalate: bool,
+    pub reason: Option<String>,
+    pub scheduled_utc: Option<NaiveDateTime>,
+}
+
+impl Reservation {
+    pub fn userid(&self) -> UserId {
+        UserId::from(self.user_id as u64)
+    }
+
+    pub fn guildid(&self) -> GuildId {
+        GuildId::from(self.guild_id as u64)
+    }
+
+    pub fn lead_times(&self) -> Option<LeadTimes> {
+        LeadTimes::parse(self.warnings.as_deref()?).ok()
+    }
+
+    pub fn target(&self) -> Target {
+        match (self.target_kind, self.target_id) {
+            (TargetKind::Channel, Some(c)) => Target::Channel(ChannelId::from(c as u64)),
+            (TargetKind::Server, _) => Target::Server,
+            (TargetKind::Role, Some(r)) => Target::Role(RoleId::from(r as u64)),
+            _ => Target::User(UserId::from(self.user_id as u64)),
+        }
+    }
+
+    // 予約ごとの指定がなければギルドの設定に従う
+    // 連続通話の上限/1日の通話時間は通話時間を区切るため常に切断する
+    pub fn action(&self, setting: &GuildSetting) -> Action {
+        match self.policy {
+            Some(Policy::Session | Policy::Budget) => Action::Disconnect,
+            _ => self.action
+                .and_then(|kind| Action::from_parts(kind, self.action_channel_id))
+                .unwrap_or_else(|| setting.action())
+        }
+    }
+
+    // ギルドの上限に対して、あと何回/何分延長できるか
+    pub fn snooze_left(&self, setting: &GuildSetting) -> (i64, i64) {
+        ((setting.snooze_count - self.snoozed_count).max(0),
+         (setting.snooze_minutes - self.snoozed_minutes).max(0))
+    }
+
+    pub fn schedule(&self) -> Option<Schedule> {
+        let tz = self.timezone.as_deref()?.parse().ok()?;
+
+        if let Some(pattern) = self.cron.as_deref() {
+            return CronSchedule::parse(pattern, tz).ok().map(|c| Schedule::Cron(Box::new(c)));
+        }
+
+        Some(Schedule::Repeat(Repeat {
+            recurrence: Recurrence::parse(self.recurrence.as_deref()?).ok()?,
+            local_time: NaiveTime::parse_from_str(self.local_time.as_deref()?, "%H:%M").ok()?,
+            tz,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Lockout {
+    pub id: i64,
+    pub user_id: i64,
+    pub until: NaiveDateTime,
+    pub attempts: i64,
+    pub channel_id: Option<i64>,
+}
+
+impl Lockout {
+    pub fn userid(&self) -> UserId {
+        UserId::from(self.user_id as u64)
+    }
+
+    pub fn timestamp(&self) -> i64 {
+        self.until.and_utc().timestamp()
+    }
+
+    // 移動先として参加を許可するチャンネル
+    pub fn allows(&self, channel_id: ChannelId) -> bool {
+        self.channel_id == Some(channel_id.0 as i64)
+    }
+}
+
+#[derive(Debug)]
+pub struct VoiceSession {
+    pub user_id: i64,
+    pub started_at: NaiveDateTime,
+    pub reservation_id: Option<i64>,
+    pub seen_at: Option<NaiveDateTime>,
+}
+
+impl VoiceSession {
+    pub fn userid(&self) -> UserId {
+        UserId::from(self.user_id as u64)
+    }
+}
+
+#[derive(Debug)]
+pub struct VoiceBudget {
+    pub daily_minutes: i64,
+    pub used_seconds: i64,
+    pub day: String,
+    pub reservation_id: Option<i64>,
+}
+
+impl VoiceBudget {
+    pub fn daily(&self) -> Duration {
+        Duration::minutes(self.daily_minutes)
+    }
+
+    // 指定日の使用時間（記録が別の日なら0）
+    pub fn used_on(&self, date: NaiveDate) -> Duration {
+        if self.day == date.to_string() {
+            Duration::seconds(self.used_seconds)
+        } else {
+            Duration::zero()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MutedMember {
+    pub user_id: i64,
+    pub mute: bool,
+    pub deaf: bool,
+}
+
+impl MutedMember {
+    pub fn userid(&self) -> UserId {
+        UserId::from(self.user_id as u64)
+    }
 }
 
 #[derive(Debug)]
 pub struct Job {
+    pub id: i64,
+    pub reservation_id: i64,
     pub naive_utc: NaiveDateTime,
     pub user_id: i64,
     pub guild_id: i64,
-    pub event_type: EventType,
+    // 実行する処理の名前（actions::ActionRegistryに登録したもの）
+    pub event_type: String,
+    pub params: Option<String>,
 }
 
 impl Job {
     #[allow(dead_code)]
-    pub fn new(naive_utc: NaiveDateTime, user_id: UserId,
-               guild_id: GuildId, event_type: EventType) -> Self {
-        Job { naive_utc, user_id: user_id.0 as i64, guild_id: guild_id.0 as i64, event_type }
+    pub fn new(reservation_id: i64, naive_utc: NaiveDateTime, user_id: UserId,
+               guild_id: GuildId, event_type: &str) -> Self {
+        Job { id: 0, reservation_id, naive_utc, user_id: user_id.0 as i64,
+              guild_id: guild_id.0 as i64, event_type: event_type.to_string(), params: None }
+    }
+
+    pub fn is(&self, event_type: &str) -> bool {
+        self.event_type == event_type
     }
 
     pub fn userid(&self) -> UserId {
@@ -47,19 +573,54 @@ impl Job {
     }
 
     pub fn timestamp(&self) -> i64 {
-        self.naive_utc.timestamp()
+        self.naive_utc.and_utc().timestamp()
+    }
+
+    pub async fn disconnect(&self, ctx: &CacheAndHttp, user_id: UserId) -> serenity::Result<Member> {
+        self.guildid().disconnect_member(&ctx.http, user_id).await
     }
 
-    pub async fn disconnect(&self, ctx: &Context) -> serenity::Result<Member> {
-        self.guildid().disconnect_member(&ctx.http, self.userid()).await
+    pub async fn move_to(&self, ctx: &CacheAndHttp, user_id: UserId, channel_id: ChannelId) -> serenity::Result<Member> {
+        self.guildid().move_member(&ctx.http, user_id, channel_id).await
     }
 
-    pub async fn direct_message<'a, F>(&self, ctx: &Context, f: F) -> serenity::Result<Message>
+    pub async fn direct_message<'a, F>(&self, ctx: &CacheAndHttp, user_id: UserId, f: F) -> serenity::Result<Message>
     where
         for<'b> F: FnOnce(&'b mut CreateMessage<'a>) -> &'b mut CreateMessage<'a>
     {
-        self.userid()
+        user_id
             .create_dm_channel(&ctx.http).await?
             .send_message(&ctx.http, f).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_string_round_trip() {
+        let targets = [Target::User(UserId(7)), Target::Channel(ChannelId(3)), Target::Server, Target::Role(RoleId(4))];
+        for target in targets {
+            assert_eq!(Target::parse(&target.to_string()), Some(target));
+        }
+        assert_eq!(Target::parse("Server:1"), None);
+        assert_eq!(Target::parse("Channel"), None);
+        assert_eq!(Target::parse("Role:x"), None);
+        assert_eq!(Target::parse("Everyone:1"), None);
+    }
+
+    #[test]
+    fn targets_in_voice() {
+        let (a, b, c) = (UserId(1), UserId(2), UserId(3));
+        let (lobby, games) = (ChannelId(10), ChannelId(11));
+        let voice_states = || [(a, Some(lobby)), (b, Some(games)), (c, None)].into_iter();
+
+        assert_eq!(Target::User(a).in_voice(voice_states()), vec![a]);
+        assert!(Target::User(c).in_voice(voice_states()).is_empty());
+        assert_eq!(Target::Channel(games).in_voice(voice_states()), vec![b]);
+        assert_eq!(Target::Server.in_voice(voice_states()), vec![a, b]);
+        // ロールは予約時に展開せず、実行時に通話中の全員からメンバーの情報で絞り込む
+        assert_eq!(Target::Role(RoleId(20)).in_voice(voice_states()), vec![a, b]);
+    }
+}
@@ -1,8 +1,14 @@
 mod job;
 mod database;
 mod commands;
+mod runner;
+mod schedule;
+mod voice;
+mod actions;
+mod time_expr;
 use database::SqliteDatabase;
-use job::EventType;
+use runner::JobRunner;
+use actions::ActionRegistry;
 
 use dotenv::dotenv;
 use serenity::{
@@ -12,72 +18,12 @@ use serenity::{
         gateway::Ready,
         application::interaction::Interaction,
         application::command::Command,
-        guild::{Guild, UnavailableGuild}
+        guild::{Guild, UnavailableGuild},
+        voice::VoiceState
     },
     prelude::*,
 };
-use std::{
-    env,
-    sync::{Arc, atomic::{AtomicBool, Ordering}}
-};
-
-pub struct JobRunner;
-
-impl TypeMapKey for JobRunner {
-    type Value = Arc<AtomicBool>;
-}
-
-impl JobRunner {
-    pub async fn run(ctx: Arc<Context>) {
-        let database = {
-            let data_read = ctx.data.read().await;
-            data_read.get::<SqliteDatabase>().unwrap().clone()
-        };
-        let is_loop_running = {
-            let data_read = ctx.data.read().await;
-            data_read.get::<JobRunner>().unwrap().clone()
-        };
-
-        while database.count_jobs().await.unwrap() > 0 {
-            let jobs = database.pop_executables().await.unwrap();
-
-            for job in jobs {
-                let ctx1 = Arc::clone(&ctx);
-                tokio::spawn(async move {
-                    println!("{:#?}", job);
-
-                    let guild = ctx1.cache.guild(job.guildid()).unwrap();
-                    if let Some(voice_state) = guild.voice_states.get(&job.userid()) {
-                        if voice_state.channel_id.is_none() {
-                            return;
-                        }
-                    } else {
-                        return;
-                    }
-
-                    match job.event_type {
-                        EventType::Disconnect => {
-                            if let Ok(_) = job.disconnect(&ctx1).await {
-                                job.direct_message(&ctx1, |m| {
-                                    m.content(format!("<t:{0}:d> <t:{0}:T>に通話を強制切断しました", job.timestamp()))
-                                }).await.unwrap();
-                            }
-                        },
-                        EventType::Notification3Min => {
-                            let _ = job.direct_message(&ctx1, |m| {
-                                m.content("3分後に通話を強制切断します")
-                            }).await.unwrap();
-                        }
-                    }
-                });
-            }
-
-            tokio::time::sleep(std::time::Duration::from_secs(3)).await;
-        }
-
-        is_loop_running.store(false, Ordering::Relaxed);
-    }
-}
+use std::{env, sync::Arc};
 
 impl TypeMapKey for SqliteDatabase {
     type Value = Arc<SqliteDatabase>;
@@ -90,17 +36,48 @@ impl EventHandler for Handler {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
         let ctx = Arc::new(ctx);
 
-        if let Interaction::ApplicationCommand(command) = interaction {
-            match command.data.name.as_str() {
-                "timezone" => commands::timezone::run(ctx.clone(), &command).await,
-                "snipe" => commands::snipe::run(ctx.clone(), &command).await,
-                "show" => commands::show::run(ctx.clone(), &command).await,
-                "clear" => commands::clear::run(ctx.clone(), &command).await,
-                _ => println!("not implemented :("),
-            }
+        match interaction {
+            Interaction::ApplicationCommand(command) => {
+                match command.data.name.as_str() {
+                    "timezone" => commands::timezone::run(ctx.clone(), &command).await,
+                    "snipe" => commands::snipe::run(ctx.clone(), &command).await,
+                    "cron" => commands::cron::run(ctx.clone(), &command).await,
+                    "show" => commands::show::run(ctx.clone(), &command).await,
+                    "clear" => commands::clear::run(ctx.clone(), &command).await,
+                    "cancel" => commands::cancel::run(ctx.clone(), &command).await,
+                    "reschedule" => commands::reschedule::run(ctx.clone(), &command).await,
+                    "status" => commands::status::run(ctx.clone(), &command).await,
+                    "config" => commands::config::run(ctx.clone(), &command).await,
+                    "budget" => commands::budget::run(ctx.clone(), &command).await,
+                    "remind" => commands::remind::run(ctx.clone(), &command).await,
+                    "schedule" | commands::schedule::USER_MENU => commands::schedule::run(ctx.clone(), &command).await,
+                    _ => println!("not implemented :("),
+                }
+            },
+            Interaction::Autocomplete(autocomplete) => {
+                match autocomplete.data.name.as_str() {
+                    "cancel" | "reschedule" => commands::cancel::autocomplete(ctx.clone(), &autocomplete).await,
+                    "snipe" => commands::snipe::autocomplete(ctx.clone(), &autocomplete).await,
+                    _ => println!("not implemented :("),
+                }
+            },
+            Interaction::MessageComponent(component) if component.data.custom_id.starts_with("snipe:") => {
+                commands::snipe::select_type(ctx.clone(), &component).await;
+            },
+            Interaction::MessageComponent(component) if component.data.custom_id.starts_with("snooze:") => {
+                actions::notification::press(ctx.clone(), &component).await;
+            },
+            Interaction::ModalSubmit(modal) if modal.data.custom_id == "schedule" => {
+                commands::schedule::submit(ctx.clone(), &modal).await;
+            },
+            _ => {}
         }
     }
 
+    async fn voice_state_update(&self, ctx: Context, old: Option<VoiceState>, new: VoiceState) {
+        voice::voice_state_update(ctx, old, new).await;
+    }
+
     async fn ready(&self, ctx: Context, ready: Ready) {
         println!("{} is connected.", ready.user.name);
 
@@ -109,8 +86,17 @@ impl EventHandler for Handler {
             commands
                 .create_application_command(|command| commands::show::register(command))
                 .create_application_command(|command| commands::clear::register(command))
+                .create_application_command(|command| commands::cancel::register(command))
+                .create_application_command(|command| commands::reschedule::register(command))
                 .create_application_command(|command| commands::snipe::register(command))
+                .create_application_command(|command| commands::cron::register(command))
                 .create_application_command(|command| commands::timezone::register(command))
+                .create_application_command(|command| commands::status::register(command))
+                .create_application_command(|command| commands::config::register(command))
+                .create_application_command(|command| commands::budget::register(command))
+                .create_application_command(|command| commands::remind::register(command))
+                .create_application_command(|command| commands::schedule::register(command))
+                .create_application_command(|command| commands::schedule::register_user_menu(command))
         })
         .await;
 
@@ -118,15 +104,11 @@ impl EventHandler for Handler {
             let data_read = ctx.data.read().await;
             data_read.get::<SqliteDatabase>().unwrap().clone()
         };
-        let is_loop_running = {
-            let data_read = ctx.data.read().await;
-            data_read.get::<JobRunner>().unwrap().clone()
-        };
 
         // ギルド更新
         if let Ok(guilds) = ready.user.guilds(&ctx.http).await {
             for guild in guilds {
-                if let Err(why) = database.insert_guild_setting(guild.id, 0).await
+                if let Err(why) = database.insert_guild_setting(guild.id, "UTC").await
                 {
                     println!("Already exists: {}, {}", guild.id, why);
                 }
@@ -137,15 +119,8 @@ impl EventHandler for Handler {
         println!("create global commands: {:#?}", commands);
         if let Ok(settings) = database.get_settings().await
         {
-            println!("{:#?}", settings);
-        }
-
-        if database.count_jobs().await.unwrap() > 0 {
-            if let Ok(_) = is_loop_running.compare_exchange(false, true,
-                                                            Ordering::Release,
-                                                            Ordering::Relaxed)
-            {
-                JobRunner::run(Arc::new(ctx)).await;
+            for setting in settings {
+                println!("guild {}: {}", setting.guild_id, setting.timezone);
             }
         }
     }
@@ -158,10 +133,12 @@ impl EventHandler for Handler {
                 data_read.get::<SqliteDatabase>().unwrap().clone()
             };
 
-            if let Err(why) = database.insert_guild_setting(guild.id, 0).await {
+            if let Err(why) = database.insert_guild_setting(guild.id, "UTC").await {
                 println!("insert guild setting: {:?}", why);
             }
         }
+        // 連続通話時間の記録を現在の通話状況に合わせる
+        voice::sync_sessions(&ctx, &guild).await;
     }
 
     async fn guild_delete(&self, ctx: Context, incomplete: UnavailableGuild, _: Option<Guild>) {
@@ -202,9 +179,21 @@ async fn main() {
     // データベース初期化
     let database = SqliteDatabase::new("./database.sqlite").await;
 
-    // 指定時刻超過のジョブを削除
-    if let Ok(_) = database.pop_executables().await {
+    // 指定時刻超過のジョブを削除（繰り返し予約は次回分を登録）
+    if let Ok((mut tx, jobs)) = database.pop_executables().await {
         println!("Delete previous jobs.");
+        match runner::schedule_recurrences(&database, &mut tx, &ActionRegistry::default(), &jobs).await {
+            Ok(()) => if let Err(why) = tx.commit().await {
+                println!("delete previous jobs: {:?}", why);
+            },
+            Err(why) => println!("schedule recurrences: {:?}", why),
+        }
+    }
+    if let Err(why) = database.expire_lockouts().await {
+        println!("expire lockouts: {:?}", why);
+    }
+    if let Err(why) = database.delete_finished_reservations().await {
+        println!("delete finished reservations: {:?}", why);
     }
 
     // クライアント初期化
@@ -214,12 +203,19 @@ async fn main() {
         .await
         .expect("Error creating client.");
 
+    let database = Arc::new(database);
+    tokio::spawn(voice::record_seen(Arc::clone(&database)));
+
+    let runner = Arc::new(JobRunner::default());
     {
         let mut data_write = client.data.write().await;
-        data_write.insert::<SqliteDatabase>(Arc::new(database));
-        data_write.insert::<JobRunner>(Arc::new(AtomicBool::new(false)));
+        data_write.insert::<SqliteDatabase>(Arc::clone(&database));
+        data_write.insert::<JobRunner>(Arc::clone(&runner));
     }
 
+    // JobRunner起動（ギルドがキャッシュに揃ってからジョブを実行する）
+    JobRunner::start(runner, Arc::clone(&client.cache_and_http), database);
+
     // Bot起動
     if let Err(why) = client.start().await {
         println!("Client error: {:?}", why);
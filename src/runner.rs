@@ -0,0 +1,192 @@
+use serenity::prelude::*;
+use serenity::CacheAndHttp;
+use chrono::{Utc, NaiveDateTime};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use crate::job::Job;
+use crate::SqliteDatabase;
+use crate::actions::{ActionRegistry, JobContext, Outcome};
+
+// 診断用のJobRunnerの状態
+#[derive(Debug, Default, Clone)]
+pub struct RunnerStatus {
+    pub running: bool,
+    pub started_at: Option<NaiveDateTime>,
+    pub restarts: u32,
+    pub executed: u64,
+    // 対象が通話中でなかったジョブ
+    pub skipped: u64,
+    pub failed: u64,
+    pub next_job: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct JobRunner {
+    status: Mutex<RunnerStatus>,
+    actions: ActionRegistry,
+    // 予約ごとの実行中ジョブ数（すべて終わるまで予約を削除しない）
+    in_flight: Mutex<HashMap<i64, usize>>,
+}
+
+impl TypeMapKey for JobRunner {
+    type Value = Arc<JobRunner>;
+}
+
+impl JobRunner {
+    pub fn status(&self) -> RunnerStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn update_status<F: FnOnce(&mut RunnerStatus)>(&self, f: F) {
+        f(&mut self.status.lock().unwrap());
+    }
+
+    // 起動時にmainから一度だけ呼ばれ、異常終了しても再起動し続ける
+    // （readyは再接続のたびに呼ばれるので、ContextではなくClientのキャッシュとHTTPを使う）
+    pub fn start(runner: Arc<JobRunner>, ctx: Arc<CacheAndHttp>, database: Arc<SqliteDatabase>) {
+        tokio::spawn(async move {
+            wait_for_cache(&ctx).await;
+
+            loop {
+                runner.update_status(|s| {
+                    s.running = true;
+                    s.started_at = Some(Utc::now().naive_utc());
+                });
+                println!("job runner started");
+
+                let result = tokio::spawn(Arc::clone(&runner).run(Arc::clone(&ctx), Arc::clone(&database))).await;
+                let why = match result {
+                    Ok(Ok(())) => "stopped".to_string(),
+                    Ok(Err(why)) => format!("database error: {}", why),
+                    Err(why) => format!("panicked: {}", why),
+                };
+                println!("job runner {}, restarting", why);
+
+                runner.update_status(|s| {
+                    s.running = false;
+                    s.restarts += 1;
+                    s.last_error = Some(why);
+                });
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            }
+        });
+    }
+
+    async fn run(self: Arc<Self>, ctx: Arc<CacheAndHttp>, database: Arc<SqliteDatabase>) -> Result<(), sqlx::Error> {
+        loop {
+            let (mut tx, jobs) = database.pop_executables().await?;
+            schedule_recurrences(&database, &mut tx, &self.actions, &jobs).await?;
+            tx.commit().await?;
+            for job in &jobs {
+                *self.in_flight.lock().unwrap().entry(job.reservation_id).or_default() += 1;
+            }
+
+            for reservation_id in database.expire_lockouts().await? {
+                if !self.in_flight.lock().unwrap().contains_key(&reservation_id) {
+                    database.delete_finished_reservation(reservation_id).await?;
+                }
+            }
+
+            for job in jobs {
+                let runner = Arc::clone(&self);
+                let ctx1 = Arc::clone(&ctx);
+                let database1 = Arc::clone(&database);
+                tokio::spawn(async move {
+                    println!("{:#?}", job);
+
+                    match runner.execute(&ctx1, &database1, &job).await {
+                        Ok(Outcome::Executed) => runner.update_status(|s| s.executed += 1),
+                        Ok(Outcome::Skipped) => runner.update_status(|s| s.skipped += 1),
+                        Err(why) => {
+                            println!("job {} failed: {}", job.id, why);
+                            runner.update_status(|s| {
+                                s.failed += 1;
+                                s.last_error = Some(why);
+                            });
+                        }
+                    }
+
+                    if runner.finish(job.reservation_id) {
+                        if let Err(why) = database1.delete_finished_reservation(job.reservation_id).await {
+                            println!("delete reservation {}: {}", job.reservation_id, why);
+                        }
+                    }
+                });
+            }
+
+            // 次のジョブの時刻まで待機（ジョブの追加/削除で再計算）
+            let next_time = database.next_job_time().await?;
+            self.update_status(|s| s.next_job = next_time);
+
+            match next_time {
+                Some(t) => {
+                    let wait = (t - Utc::now().naive_utc()).to_std().unwrap_or_default();
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {},
+                        _ = database.job_changed().notified() => {},
+                    }
+                },
+                None => database.job_changed().notified().await,
+            }
+        }
+    }
+
+    // 予約の実行中ジョブを1つ減らし、最後の1つだったかを返す
+    fn finish(&self, reservation_id: i64) -> bool {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let Some(count) = in_flight.get_mut(&reservation_id) else {
+            return true;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return false;
+        }
+        in_flight.remove(&reservation_id);
+        true
+    }
+
+    async fn execute(&self, ctx: &CacheAndHttp, database: &Arc<SqliteDatabase>, job: &Job) -> Result<Outcome, String> {
+        // 登録されていない種類（削除された機能など）のジョブは実行せずに失敗として記録する
+        let action = self.actions.get(&job.event_type)
+            .ok_or_else(|| format!("unknown job action: {}", job.event_type))?;
+
+        let job_ctx = JobContext::new(ctx, database, job).await?;
+        action.execute(&job_ctx).await
+    }
+}
+
+// ギルドがキャッシュに揃うまで待つ（起動直後のジョブがギルドを見つけられずに失敗しないように）
+// 障害でいつまでも揃わないギルドがあっても、1分で待つのをやめる
+async fn wait_for_cache(ctx: &CacheAndHttp) {
+    for _ in 0..60 {
+        let ready = ctx.cache.current_user_id().0 != 0 && ctx.cache.unavailable_guilds().is_empty();
+        if ready {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+}
+
+// 繰り返し予約の次回分を登録（登録する内容は各処理が決める）
+// txはジョブを取り出したトランザクションで、次回分と一緒に確定する
+pub async fn schedule_recurrences(database: &SqliteDatabase, tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+                                  actions: &ActionRegistry, jobs: &[Job]) -> Result<(), sqlx::Error> {
+    for job in jobs {
+        let Some(action) = actions.get(&job.event_type) else {
+            continue;
+        };
+        let Some(reservation) = database.get_reservation(job.reservation_id).await? else {
+            continue;
+        };
+
+        // 延長や/rescheduleでずれた実行時刻ではなく、本来の時刻から次回を求める
+        // （23:50の予約を日付をまたいで延長しても翌日分を飛ばさない）
+        let prev = reservation.scheduled_utc.unwrap_or(job.naive_utc);
+        let Some(next) = reservation.schedule().and_then(|s| s.next_after(prev)) else {
+            continue;
+        };
+        action.schedule_next(database, tx, job, next).await?;
+    }
+    Ok(())
+}
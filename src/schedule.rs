@@ -0,0 +1,500 @@
+use chrono::{Utc, Duration, Datelike, Weekday, NaiveDate, NaiveTime, NaiveDateTime,
+             TimeZone, LocalResult, Offset};
+use chrono_tz::Tz;
+use croner::Cron;
+use regex::Regex;
+use std::fmt;
+use std::sync::OnceLock;
+
+// cron式の実行間隔の下限（分）
+const CRON_MIN_INTERVAL: i64 = 10;
+// 切断前通知の上限
+const MAX_LEAD_TIMES: usize = 5;
+const MAX_LEAD_MINUTES: u32 = 24 * 60;
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu,
+    Weekday::Fri, Weekday::Sat, Weekday::Sun,
+];
+
+// 現地時刻をUTCに変換（夏時間の切り替えを考慮）
+pub fn local_to_utc(tz: Tz, naive_local: NaiveDateTime) -> NaiveDateTime {
+    match tz.from_local_datetime(&naive_local) {
+        LocalResult::Single(dt) => dt.naive_utc(),
+        // 重複する時刻（夏時間終了）は早い方を採用
+        LocalResult::Ambiguous(earliest, _) => earliest.naive_utc(),
+        // 存在しない時刻（夏時間開始）は直前のオフセットで解釈し、切り替え後にずらす
+        LocalResult::None => {
+            let offset = (1..=96)
+                .find_map(|i| tz.offset_from_local_datetime(&(naive_local - Duration::minutes(15 * i))).latest())
+                .map_or(0, |o| o.fix().local_minus_utc());
+            naive_local - Duration::seconds(offset.into())
+        }
+    }
+}
+
+// UTCの時刻の現地日付
+pub fn local_date(tz: Tz, naive_utc: NaiveDateTime) -> NaiveDate {
+    naive_utc.and_utc().with_timezone(&tz).date_naive()
+}
+
+// 現地日付の0時（UTC）
+pub fn start_of_day(tz: Tz, date: NaiveDate) -> NaiveDateTime {
+    local_to_utc(tz, date.and_time(NaiveTime::MIN))
+}
+
+// 残り時間の表示 (例: 1時間5分, 30秒)
+pub fn describe_duration(duration: Duration) -> String {
+    let secs = duration.num_seconds().max(0);
+    let (h, m, s) = (secs / 3600, secs % 3600 / 60, secs % 60);
+
+    match (h, m) {
+        (0, 0) => format!("{}秒", s),
+        (0, m) => format!("{}分", m + (s >= 30) as i64),
+        (h, 0) => format!("{}時間", h),
+        (h, m) => format!("{}時間{}分", h, m),
+    }
+}
+
+fn weekday_ja(weekday: Weekday) -> char {
+    match weekday {
+        Weekday::Mon => '月',
+        Weekday::Tue => '火',
+        Weekday::Wed => '水',
+        Weekday::Thu => '木',
+        Weekday::Fri => '金',
+        Weekday::Sat => '土',
+        Weekday::Sun => '日',
+    }
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    if let Some(weekday) = WEEKDAYS.iter().find(|&&w| s.starts_with(weekday_ja(w))) {
+        return Some(*weekday);
+    }
+    s.parse().ok()
+}
+
+static RE_EVERY: OnceLock<Regex> = OnceLock::new();
+static RE_LEAD: OnceLock<Regex> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Recurrence {
+    Daily,
+    Weekly(Vec<Weekday>),
+    EveryNDays(u32),
+}
+
+impl fmt::Display for Recurrence {
+    // Recurrence::parseで読み戻せる形式で出力
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Daily => write!(f, "daily"),
+            Self::Weekly(weekdays) => {
+                let names: Vec<String> = weekdays.iter().map(|w| w.to_string().to_lowercase()).collect();
+                write!(f, "{}", names.join(","))
+            },
+            Self::EveryNDays(n) => write!(f, "every {} days", n),
+        }
+    }
+}
+
+impl Recurrence {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim().to_lowercase();
+        let re_every = RE_EVERY.get_or_init(|| {
+            Regex::new(r"^(?:every\s*(\d+)\s*days?|(\d+)\s*日(?:ごと|毎))$").unwrap()
+        });
+
+        if let Some(caps) = re_every.captures(&input) {
+            let n: u32 = caps.get(1).or(caps.get(2)).unwrap().as_str().parse()
+                .map_err(|_| "日数が大きすぎます".to_string())?;
+            return match n {
+                0 => Err("日数は1以上を指定してください".to_string()),
+                1 => Ok(Self::Daily),
+                n if n > 365 => Err("日数は365以下を指定してください".to_string()),
+                n => Ok(Self::EveryNDays(n)),
+            };
+        }
+
+        match input.as_str() {
+            "daily" | "毎日" => return Ok(Self::Daily),
+            "weekdays" | "平日" => return Ok(Self::Weekly(WEEKDAYS[..5].to_vec())),
+            "weekends" | "土日" | "週末" => return Ok(Self::Weekly(WEEKDAYS[5..].to_vec())),
+            _ => {}
+        }
+
+        // 曜日の列挙 (mon,wed,fri / 月水金 / 月曜日,水曜日)
+        // 「月曜日」の「日」を日曜と読まないよう、1文字ずつに分ける前に曜日/曜を取り除く
+        let tokens: Vec<String> = if input.is_ascii() {
+            input.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|t| !t.is_empty())
+                .map(|t| t.to_string())
+                .collect()
+        } else {
+            input.replace("曜日", "").replace('曜', "").chars()
+                .filter(|c| !matches!(c, ',' | '、' | '・' | ' '))
+                .map(|c| c.to_string())
+                .collect()
+        };
+
+        let mut weekdays = Vec::new();
+        for token in tokens.iter() {
+            match parse_weekday(token) {
+                Some(w) => weekdays.push(w),
+                None => return Err(format!("繰り返し「{}」を認識できません (例: daily, weekdays, mon,wed,fri, 月水金, 3日ごと)", input))
+            }
+        }
+        if weekdays.is_empty() {
+            return Err("繰り返しの指定が空です".to_string());
+        }
+        weekdays.sort_by_key(|w| w.num_days_from_monday());
+        weekdays.dedup();
+
+        if weekdays.len() == 7 {
+            Ok(Self::Daily)
+        } else {
+            Ok(Self::Weekly(weekdays))
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Daily => "毎日".to_string(),
+            Self::Weekly(weekdays) => {
+                let names: Vec<String> = weekdays.iter().map(|&w| weekday_ja(w).to_string()).collect();
+                format!("毎週 {}", names.join("・"))
+            },
+            Self::EveryNDays(n) => format!("{}日ごと", n),
+        }
+    }
+
+    // 指定日以降で最初に該当する日
+    fn first_date_on_or_after(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Self::Weekly(weekdays) => (0..7)
+                .map(|i| date + Duration::days(i))
+                .find(|d| weekdays.contains(&d.weekday()))
+                .unwrap_or(date),
+            _ => date,
+        }
+    }
+
+    // 指定日の次に該当する日
+    fn next_date(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Self::Daily => date + Duration::days(1),
+            Self::Weekly(_) => self.first_date_on_or_after(date + Duration::days(1)),
+            Self::EveryNDays(n) => date + Duration::days((*n).into()),
+        }
+    }
+}
+
+// 切断前通知のタイミング（切断の何分前か）
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeadTimes(Vec<u32>);
+
+impl Default for LeadTimes {
+    fn default() -> Self {
+        LeadTimes(vec![3])
+    }
+}
+
+impl fmt::Display for LeadTimes {
+    // LeadTimes::parseで読み戻せる形式で出力
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let minutes: Vec<String> = self.0.iter().map(|m| m.to_string()).collect();
+        write!(f, "{}", minutes.join(","))
+    }
+}
+
+impl LeadTimes {
+    // 例: "30m,10m,1m", "1h15m 15分", "なし"（空白/カンマ区切りの各項目が1つの通知）
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim().to_lowercase();
+        if input.is_empty() || matches!(input.as_str(), "none" | "なし" | "0") {
+            return Ok(LeadTimes(Vec::new()));
+        }
+
+        let re_lead = RE_LEAD.get_or_init(|| {
+            Regex::new(r"^(?:(\d+)\s*(?:h|hours?|時間))?\s*(?:(\d+)\s*(?:m|mins?|minutes?|分)?)?$").unwrap()
+        });
+        let mut minutes = Vec::new();
+        for item in input.split(|c: char| c == ',' || c == '、' || c.is_whitespace()).filter(|i| !i.is_empty()) {
+            let caps = re_lead.captures(item)
+                .filter(|caps| caps.get(1).is_some() || caps.get(2).is_some())
+                .ok_or_else(|| format!("通知タイミング「{}」を認識できません (例: 30m,10m,1m)", item))?;
+            let h: u32 = caps.get(1).map_or(Ok(0), |h| h.as_str().parse()).map_err(|_| "時間が大きすぎます".to_string())?;
+            let m: u32 = caps.get(2).map_or(Ok(0), |m| m.as_str().parse()).map_err(|_| "時間が大きすぎます".to_string())?;
+            let total = h.saturating_mul(60).saturating_add(m);
+
+            if total == 0 || total > MAX_LEAD_MINUTES {
+                return Err(format!("通知タイミングは1分から{}時間の間で指定してください", MAX_LEAD_MINUTES / 60));
+            }
+            minutes.push(total);
+        }
+
+        minutes.sort_unstable_by(|a, b| b.cmp(a));
+        minutes.dedup();
+        if minutes.len() > MAX_LEAD_TIMES {
+            return Err(format!("通知タイミングは{}個まで指定できます", MAX_LEAD_TIMES));
+        }
+        Ok(LeadTimes(minutes))
+    }
+
+    pub fn describe(&self) -> String {
+        if self.0.is_empty() {
+            return "なし".to_string();
+        }
+        let items: Vec<String> = self.0.iter()
+            .map(|&m| format!("{}前", describe_duration(Duration::minutes(m.into()))))
+            .collect();
+        items.join("・")
+    }
+
+    pub fn durations(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.0.iter().map(|&m| Duration::minutes(m.into()))
+    }
+}
+
+// 繰り返し予約（現地時刻で評価する）
+#[derive(Debug, Clone)]
+pub struct Repeat {
+    pub recurrence: Recurrence,
+    pub local_time: NaiveTime,
+    pub tz: Tz,
+}
+
+impl Repeat {
+    // 指定時刻（UTC）以降で最初の発生時刻
+    pub fn first_on_or_after(&self, naive_utc: NaiveDateTime) -> NaiveDateTime {
+        let mut date = self.recurrence.first_date_on_or_after(
+            naive_utc.and_utc().with_timezone(&self.tz).date_naive());
+
+        loop {
+            let next = local_to_utc(self.tz, date.and_time(self.local_time));
+            if next >= naive_utc {
+                return next;
+            }
+            date = self.recurrence.next_date(date);
+        }
+    }
+
+    // 前回の発生時刻（UTC）の次の発生時刻（停止中に過ぎた回は飛ばす）
+    pub fn next_after(&self, prev_utc: NaiveDateTime) -> NaiveDateTime {
+        let now = Utc::now().naive_utc();
+        let mut date = prev_utc.and_utc().with_timezone(&self.tz).date_naive();
+
+        loop {
+            date = self.recurrence.next_date(date);
+            let next = local_to_utc(self.tz, date.and_time(self.local_time));
+            if next > now {
+                return next;
+            }
+        }
+    }
+}
+
+// cron式による予約（タイムゾーンの現地時刻で評価する）
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    pub pattern: String,
+    pub tz: Tz,
+    cron: Cron,
+}
+
+impl CronSchedule {
+    pub fn parse(pattern: &str, tz: Tz) -> Result<Self, String> {
+        let pattern = pattern.split_whitespace().collect::<Vec<_>>().join(" ");
+        if pattern.split(' ').count() != 5 {
+            return Err("cron式は「分 時 日 月 曜日」の5項目で指定してください (例: 0 23 * * 1-5)".to_string());
+        }
+
+        let cron = Cron::new(&pattern)
+            .parse()
+            .map_err(|why| format!("cron式「{}」を解釈できません: {}", pattern, why))?;
+        let schedule = CronSchedule { pattern, tz, cron };
+
+        let upcoming = schedule.upcoming(2);
+        match upcoming.as_slice() {
+            [] => Err(format!("cron式「{}」に該当する日時がありません", schedule.pattern)),
+            [first, second] if *second - *first < Duration::minutes(CRON_MIN_INTERVAL) =>
+                Err(format!("実行間隔が短すぎます ({}分以上の間隔が必要です)", CRON_MIN_INTERVAL)),
+            _ => Ok(schedule)
+        }
+    }
+
+    // 指定時刻（UTC）と現在時刻の両方より後の次の発生時刻
+    pub fn next_after(&self, naive_utc: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = naive_utc.max(Utc::now().naive_utc());
+        self.cron
+            .find_next_occurrence(&self.tz.from_utc_datetime(&start), false)
+            .ok()
+            .map(|dt| dt.naive_utc())
+    }
+
+    // 現在以降のn回分の発生時刻
+    pub fn upcoming(&self, n: usize) -> Vec<NaiveDateTime> {
+        let mut times: Vec<NaiveDateTime> = Vec::new();
+        let mut last = Utc::now().naive_utc();
+        while times.len() < n {
+            match self.next_after(last) {
+                Some(next) => {
+                    times.push(next);
+                    last = next;
+                },
+                None => break
+            }
+        }
+        times
+    }
+}
+
+// ギルドの静粛時間（現地時刻、日付をまたいでもよい）
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Result<Self, String> {
+        if start == end {
+            return Err("静粛時間の開始と終了が同じ時刻です".to_string());
+        }
+        Ok(QuietHours { start, end })
+    }
+
+    pub fn contains(&self, local_time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= local_time && local_time < self.end
+        } else {
+            self.start <= local_time || local_time < self.end
+        }
+    }
+
+    // 現在の静粛時間が終わる時刻（UTC）
+    pub fn end_after(&self, tz: Tz, naive_utc: NaiveDateTime) -> NaiveDateTime {
+        let local = naive_utc.and_utc().with_timezone(&tz).naive_local();
+        let mut end = local.date().and_time(self.end);
+        if end <= local {
+            end += Duration::days(1);
+        }
+        local_to_utc(tz, end)
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{}〜{}", self.start.format("%H:%M"), self.end.format("%H:%M"))
+    }
+}
+
+// 繰り返し予約のスケジュール
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Repeat(Repeat),
+    Cron(Box<CronSchedule>),
+}
+
+impl Schedule {
+    pub fn next_after(&self, prev_utc: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self {
+            Self::Repeat(repeat) => Some(repeat.next_after(prev_utc)),
+            Self::Cron(cron) => cron.next_after(prev_utc),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Repeat(repeat) => format!("{} {} ({})", repeat.recurrence.describe(),
+                                            repeat.local_time.format("%H:%M"), repeat.tz.name()),
+            Self::Cron(cron) => format!("cron `{}` ({})", cron.pattern, cron.tz.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recurrence_weekday_names() {
+        assert_eq!(Recurrence::parse("月曜日"), Ok(Recurrence::Weekly(vec![Weekday::Mon])));
+        assert_eq!(Recurrence::parse("月曜"), Ok(Recurrence::Weekly(vec![Weekday::Mon])));
+        assert_eq!(Recurrence::parse("月曜日,水曜日"), Ok(Recurrence::Weekly(vec![Weekday::Mon, Weekday::Wed])));
+        assert_eq!(Recurrence::parse("日曜日"), Ok(Recurrence::Weekly(vec![Weekday::Sun])));
+        assert_eq!(Recurrence::parse("月水金"), Ok(Recurrence::Weekly(vec![Weekday::Mon, Weekday::Wed, Weekday::Fri])));
+        assert_eq!(Recurrence::parse("mon,wed"), Ok(Recurrence::Weekly(vec![Weekday::Mon, Weekday::Wed])));
+    }
+
+    #[test]
+    fn lead_times_items() {
+        assert_eq!(LeadTimes::parse("1h15m 15分").map(|l| l.to_string()), Ok("75,15".to_string()));
+        assert_eq!(LeadTimes::parse("30m,10m,1m").map(|l| l.to_string()), Ok("30,10,1".to_string()));
+    }
+
+    #[test]
+    fn recurrence_every_n_days() {
+        assert_eq!(Recurrence::parse("3日ごと"), Ok(Recurrence::EveryNDays(3)));
+        assert_eq!(Recurrence::parse("every 1 day"), Ok(Recurrence::Daily));
+        assert!(Recurrence::parse("0日ごと").is_err());
+    }
+
+    fn hm(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn quiet_hours_across_midnight() {
+        let quiet = QuietHours::new(hm(23, 0), hm(6, 30)).unwrap();
+        assert!(quiet.contains(hm(23, 0)));
+        assert!(quiet.contains(hm(0, 0)));
+        assert!(quiet.contains(hm(6, 29)));
+        assert!(!quiet.contains(hm(6, 30)));
+        assert!(!quiet.contains(hm(12, 0)));
+        assert!(!quiet.contains(hm(22, 59)));
+
+        let quiet = QuietHours::new(hm(1, 0), hm(5, 0)).unwrap();
+        assert!(quiet.contains(hm(1, 0)));
+        assert!(!quiet.contains(hm(5, 0)));
+        assert!(!quiet.contains(hm(23, 0)));
+
+        assert!(QuietHours::new(hm(1, 0), hm(1, 0)).is_err());
+    }
+
+    #[test]
+    fn quiet_hours_end_after() {
+        let quiet = QuietHours::new(hm(23, 0), hm(6, 0)).unwrap();
+        let tokyo = chrono_tz::Asia::Tokyo;
+        let at = |d, h, m| NaiveDate::from_ymd_opt(2024, 1, d).unwrap().and_time(hm(h, m));
+
+        // 東京の23:30（UTC 14:30）なら翌朝6:00（UTC 21:00）に終わる
+        assert_eq!(quiet.end_after(tokyo, at(1, 14, 30)), at(1, 21, 0));
+        // 東京の1:00（前日のUTC 16:00）なら同じ日の6:00に終わる
+        assert_eq!(quiet.end_after(tokyo, at(1, 16, 0)), at(1, 21, 0));
+    }
+
+    #[test]
+    fn cron_errors() {
+        let err = |pattern| CronSchedule::parse(pattern, Tz::UTC).unwrap_err();
+        assert!(err("0 23 * *").contains("5項目"));
+        assert!(err("0 23 * * 1-5 2024").contains("5項目"));
+        assert!(err("61 23 * * *").contains("解釈できません"));
+        assert!(CronSchedule::parse("0 0 30 2 *", Tz::UTC).is_err());
+    }
+
+    #[test]
+    fn cron_minimum_interval() {
+        assert!(CronSchedule::parse("*/5 * * * *", Tz::UTC).unwrap_err().contains("10分以上"));
+        assert!(CronSchedule::parse("0,5 23 * * *", Tz::UTC).is_err());
+        assert!(CronSchedule::parse("*/10 * * * *", Tz::UTC).is_ok());
+        // 余分な空白は詰めて保存する
+        assert_eq!(CronSchedule::parse(" 0  23 * * 1-5 ", Tz::UTC).unwrap().pattern, "0 23 * * 1-5");
+    }
+
+    #[test]
+    fn cron_uses_local_time() {
+        let schedule = CronSchedule::parse("0 9 * * *", chrono_tz::Asia::Tokyo).unwrap();
+        let next = schedule.next_after(Utc::now().naive_utc()).unwrap();
+        assert_eq!(next.time(), NaiveTime::from_hms_opt(0, 0, 0).unwrap());
+    }
+}
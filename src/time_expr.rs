@@ -0,0 +1,697 @@
+use chrono::{DateTime, Utc, Duration, Datelike, Weekday, NaiveDate, NaiveTime, NaiveDateTime};
+use chrono_tz::Tz;
+use std::fmt;
+use crate::schedule::{local_to_utc, describe_duration};
+
+// エラー時に表示する入力例
+pub const TIME_EXAMPLES: &str = "例: `23:30`, `23時半`, `午前1時`, `11:30pm`, `明日 1時`, `tomorrow 1:00`, `金曜 22時`, `10/31 22:00`";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeError {
+    Empty,
+    // 解釈できなかった部分
+    Unrecognized(String),
+    HourOutOfRange(u32),
+    MinuteOutOfRange(u32),
+    // 午前/午後/am/pmと13時以降の組み合わせ
+    MeridiemHour(u32),
+    InvalidDate { year: Option<i32>, month: u32, day: u32 },
+    // 日付を指定したが時刻（時）がない
+    MissingHour,
+    // 30m/1hのような時間後の指定と紛らわしい入力
+    DurationLike(String),
+    Past,
+}
+
+impl fmt::Display for TimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "時刻を入力してください"),
+            Self::Unrecognized(rest) => write!(f, "「{}」を時刻として認識できません", rest),
+            Self::HourOutOfRange(h) => write!(f, "{}時は範囲外です (0〜23時)", h),
+            Self::MinuteOutOfRange(m) => write!(f, "{}分は範囲外です (0〜59分)", m),
+            Self::MeridiemHour(h) => write!(f, "午前/午後 (am/pm) には12時までを指定してください ({}時)", h),
+            Self::InvalidDate { year: Some(y), month, day } => write!(f, "{}年{}月{}日は存在しません", y, month, day),
+            Self::InvalidDate { year: None, month, day } => write!(f, "{}月{}日は存在しません", month, day),
+            Self::MissingHour => write!(f, "日付を指定するときは時刻も指定してください"),
+            Self::DurationLike(input) =>
+                write!(f, "「{}」は時間後の指定と紛らわしいため時刻として使えません (1:00, 1時 のように指定してください)", input),
+            Self::Past => write!(f, "過去の時刻は指定できません"),
+        }
+    }
+}
+
+impl TimeError {
+    // Botの返信用（エラー内容と入力例）
+    pub fn help(&self) -> String {
+        format!("{}\n{}", self, TIME_EXAMPLES)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Day {
+    // 指定なし（次にその時刻になる日）
+    Next,
+    // 今日からの日数
+    Offset(i64),
+    Weekday(Weekday),
+    Date { year: Option<i32>, month: u32, day: u32 },
+}
+
+// 絶対時刻の指定
+// （30m/1hのような単位付きの数字は時間の指定と紛らわしいので時刻としては受け付けない）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeExpr {
+    day: Day,
+    clock: NaiveTime,
+}
+
+const DAY_WORDS: [(&str, i64); 10] = [
+    ("today", 0), ("今日", 0), ("きょう", 0), ("本日", 0),
+    ("tomorrow", 1), ("明日", 1), ("あした", 1), ("あす", 1),
+    ("明後日", 2), ("あさって", 2),
+];
+
+const WEEKDAY_WORDS: [(&str, Weekday); 7] = [
+    ("monday", Weekday::Mon), ("tuesday", Weekday::Tue), ("wednesday", Weekday::Wed),
+    ("thursday", Weekday::Thu), ("friday", Weekday::Fri), ("saturday", Weekday::Sat),
+    ("sunday", Weekday::Sun),
+];
+
+const WEEKDAY_JA: [(char, Weekday); 7] = [
+    ('月', Weekday::Mon), ('火', Weekday::Tue), ('水', Weekday::Wed), ('木', Weekday::Thu),
+    ('金', Weekday::Fri), ('土', Weekday::Sat), ('日', Weekday::Sun),
+];
+
+// 全角英数字/記号を半角にし、小文字にそろえる
+fn normalize(input: &str) -> String {
+    input.trim()
+        .chars()
+        .map(|c| match c {
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            '\u{3000}' => ' ',
+            c => c,
+        })
+        .collect::<String>()
+        .to_lowercase()
+}
+
+struct Scanner {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Scanner {
+    fn rest(&self) -> String {
+        self.chars[self.pos..].iter().collect()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, word: &str) -> bool {
+        let word: Vec<char> = word.chars().collect();
+        if self.chars[self.pos..].starts_with(&word) {
+            self.pos += word.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_any(&mut self, words: &[&str]) -> bool {
+        words.iter().any(|word| self.eat(word))
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        // 桁数が多すぎる入力は数値として扱わない
+        match self.pos - start {
+            1..=4 => self.chars[start..self.pos].iter().collect::<String>().parse().ok(),
+            0 => None,
+            _ => {
+                self.pos = start;
+                None
+            }
+        }
+    }
+
+    // 今日/明日/曜日/日付
+    fn day(&mut self) -> Result<Option<Day>, TimeError> {
+        for (word, offset) in DAY_WORDS {
+            if self.eat(word) {
+                return Ok(Some(Day::Offset(offset)));
+            }
+        }
+        for (word, weekday) in WEEKDAY_WORDS {
+            // monday/mon
+            if self.eat(word) || self.eat(&word[..3]) {
+                return Ok(Some(Day::Weekday(weekday)));
+            }
+        }
+        for (c, weekday) in WEEKDAY_JA {
+            // 金曜日/金曜/(金)
+            let long = format!("{}曜", c);
+            let paren = format!("({})", c);
+            if self.eat(&long) {
+                self.eat("日");
+                return Ok(Some(Day::Weekday(weekday)));
+            }
+            if self.eat(&paren) {
+                return Ok(Some(Day::Weekday(weekday)));
+            }
+        }
+        self.date()
+    }
+
+    // 10/31, 2024/10/31, 10-31, 10月31日, 2024年10月31日
+    fn date(&mut self) -> Result<Option<Day>, TimeError> {
+        let start = self.pos;
+        let Some(first) = self.number() else {
+            return Ok(None);
+        };
+
+        let (year, month, day) = if self.eat("年") {
+            let month = self.number().filter(|_| self.eat("月"));
+            let day = month.and_then(|_| self.number()).filter(|_| self.eat("日"));
+            match (month, day) {
+                (Some(month), Some(day)) => (Some(first as i32), month, day),
+                _ => return Err(TimeError::Unrecognized(self.rest_from(start)))
+            }
+        } else if self.eat("月") {
+            match self.number().filter(|_| self.eat("日")) {
+                Some(day) => (None, first, day),
+                None => return Err(TimeError::Unrecognized(self.rest_from(start)))
+            }
+        } else if self.eat_any(&["/", "-"]) {
+            let Some(second) = self.number() else {
+                return Err(TimeError::Unrecognized(self.rest_from(start)));
+            };
+            if self.eat_any(&["/", "-"]) {
+                match self.number() {
+                    Some(day) => (Some(first as i32), second, day),
+                    None => return Err(TimeError::Unrecognized(self.rest_from(start)))
+                }
+            } else {
+                (None, first, second)
+            }
+        } else {
+            // 日付ではない（時刻の数字）
+            self.pos = start;
+            return Ok(None);
+        };
+
+        if NaiveDate::from_ymd_opt(year.unwrap_or(2000), month, day).is_none() {
+            return Err(TimeError::InvalidDate { year, month, day });
+        }
+        // 曜日の併記 (10/31(金)) は読み飛ばす
+        self.skip_spaces();
+        for (c, _) in WEEKDAY_JA {
+            self.eat(&format!("({})", c));
+        }
+        Ok(Some(Day::Date { year, month, day }))
+    }
+
+    fn rest_from(&self, start: usize) -> String {
+        self.chars[start..].iter().collect()
+    }
+
+    // 23:30, 23時半, 23時30分, 午前1時, 11:30pm, 正午
+    fn clock(&mut self) -> Result<Option<NaiveTime>, TimeError> {
+        if self.eat_any(&["正午", "noon"]) {
+            return Ok(Some(NaiveTime::from_hms_opt(12, 0, 0).unwrap()));
+        }
+        if self.eat("midnight") {
+            return Ok(Some(NaiveTime::MIN));
+        }
+
+        let prefix = if self.eat("午前") {
+            Some(false)
+        } else if self.eat("午後") {
+            Some(true)
+        } else {
+            None
+        };
+        self.skip_spaces();
+
+        let start = self.pos;
+        let Some(first) = self.number() else {
+            return match prefix {
+                Some(_) => Err(TimeError::Unrecognized(self.rest())),
+                None => Ok(None)
+            };
+        };
+        if self.unit(&[Unit::Day, Unit::Hour, Unit::Minute, Unit::Second]).is_some() {
+            return Err(TimeError::DurationLike(self.rest_from(start).trim().to_string()));
+        }
+
+        let minute = if self.eat(":") {
+            self.number().unwrap_or(0)
+        } else if self.eat("時") {
+            if self.eat("半") {
+                30
+            } else {
+                let minute = self.number();
+                if minute.is_some() && !self.eat("分") {
+                    return Err(TimeError::Unrecognized(self.rest()));
+                }
+                minute.unwrap_or(0)
+            }
+        } else {
+            0
+        };
+
+        self.skip_spaces();
+        let suffix = if self.eat_any(&["a.m.", "am"]) {
+            Some(false)
+        } else if self.eat_any(&["p.m.", "pm"]) {
+            Some(true)
+        } else {
+            None
+        };
+        let hour = match (prefix, suffix) {
+            (Some(_), Some(_)) => return Err(TimeError::Unrecognized(self.rest())),
+            (Some(pm), None) | (None, Some(pm)) => {
+                if first > 12 {
+                    return Err(TimeError::MeridiemHour(first));
+                }
+                // 午前0時/12am = 0時、午後0時/12pm = 12時
+                match (pm, first) {
+                    (false, 12) => 0,
+                    (true, 12) => 12,
+                    (true, h) => h + 12,
+                    (false, h) => h,
+                }
+            },
+            (None, None) => first
+        };
+
+        if hour > 23 {
+            return Err(TimeError::HourOutOfRange(hour));
+        }
+        if minute > 59 {
+            return Err(TimeError::MinuteOutOfRange(minute));
+        }
+        Ok(Some(NaiveTime::from_hms_opt(hour, minute, 0).unwrap()))
+    }
+}
+
+impl TimeExpr {
+    pub fn parse(input: &str) -> Result<Self, TimeError> {
+        // 入力の途中で終わった (1時30, 午後) 場合は入力全体を示す
+        Self::parse_normalized(&normalize(input)).map_err(|why| match why {
+            TimeError::Unrecognized(rest) if rest.trim().is_empty() => TimeError::Unrecognized(input.trim().to_string()),
+            why => why
+        })
+    }
+
+    fn parse_normalized(input: &str) -> Result<Self, TimeError> {
+        let mut scanner = Scanner { chars: input.chars().collect(), pos: 0 };
+        scanner.skip_spaces();
+        if scanner.peek().is_none() {
+            return Err(TimeError::Empty);
+        }
+
+        // 日付は時刻の前後どちらにも書ける
+        let mut day = scanner.day()?;
+        scanner.skip_spaces();
+        let clock = scanner.clock()?;
+        scanner.skip_spaces();
+        if day.is_none() && clock.is_some() {
+            day = scanner.day()?;
+            scanner.skip_spaces();
+        }
+
+        if scanner.peek().is_some() {
+            return Err(TimeError::Unrecognized(scanner.rest()));
+        }
+        match (day, clock) {
+            (Some(_), None) => Err(TimeError::MissingHour),
+            (None, None) => Err(TimeError::Unrecognized(scanner.rest())),
+            (day, Some(clock)) => Ok(TimeExpr { day: day.unwrap_or(Day::Next), clock }),
+        }
+    }
+
+    // 日付の指定がない時刻 (静粛時間などの毎日の時刻)
+    pub fn time_of_day(&self) -> Option<NaiveTime> {
+        match self.day {
+            Day::Next => Some(self.clock),
+            _ => None
+        }
+    }
+
+    // 指定のタイムゾーンで解釈し、UTCの時刻を返す
+    pub fn resolve(&self, tz: Tz, now: DateTime<Utc>) -> Result<NaiveDateTime, TimeError> {
+        let local_now = now.with_timezone(&tz).naive_local();
+        let today = local_now.date();
+
+        let time = self.clock;
+        let local = match self.day {
+            Day::Next => {
+                let t = today.and_time(time);
+                if t <= local_now { t + Duration::days(1) } else { t }
+            },
+            Day::Offset(days) => (today + Duration::days(days)).and_time(time),
+            Day::Weekday(weekday) => {
+                let days = (7 + weekday.num_days_from_monday() - today.weekday().num_days_from_monday()) % 7;
+                let t = (today + Duration::days(days.into())).and_time(time);
+                if t <= local_now { t + Duration::days(7) } else { t }
+            },
+            Day::Date { year, month, day } => {
+                let date = |y: i32| NaiveDate::from_ymd_opt(y, month, day)
+                    .ok_or(TimeError::InvalidDate { year: Some(y), month, day });
+                let t = date(year.unwrap_or(today.year()))?.and_time(time);
+                // 年の指定がなければ次に来るその日
+                match year {
+                    None if t <= local_now => date(today.year() + 1)?.and_time(time),
+                    _ => t
+                }
+            },
+        };
+
+        if local <= local_now {
+            return Err(TimeError::Past);
+        }
+        Ok(local_to_utc(tz, local))
+    }
+}
+
+// エラー時に表示する相対指定の入力例
+pub const DURATION_EXAMPLES: &str = "例: `30m`, `90min`, `1.5h`, `1h30m`, `1時間半`, `45秒`, `2d`, `1:30`, `PT1H30M`";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DurationError {
+    Empty,
+    // 解釈できなかった部分
+    Unrecognized(String),
+    // 単位の重複や順序の誤り (例: 30m1h)
+    UnitOrder(String),
+    // h:mm:ssの分/秒が60以上
+    OutOfRange(u32),
+    Zero,
+    // 上限を超えている（上限の時間）
+    TooLong(Duration),
+}
+
+impl fmt::Display for DurationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "時間を入力してください"),
+            Self::Unrecognized(rest) => write!(f, "「{}」を時間として認識できません", rest),
+            Self::UnitOrder(rest) => write!(f, "単位は大きい順に1回ずつ指定してください (「{}」)", rest),
+            Self::OutOfRange(n) => write!(f, "時:分:秒の分/秒に{}は指定できません (0〜59)", n),
+            Self::Zero => write!(f, "0より長い時間を指定してください"),
+            Self::TooLong(max) if max.num_seconds() % 86400 == 0 =>
+                write!(f, "{}日より先は予約できません", max.num_days()),
+            Self::TooLong(max) => write!(f, "{}より先は予約できません", describe_duration(*max)),
+        }
+    }
+}
+
+impl DurationError {
+    // Botの返信用（エラー内容と入力例）
+    pub fn help(&self) -> String {
+        format!("{}\n{}", self, DURATION_EXAMPLES)
+    }
+
+    // 時間の書式としては読めたかどうか
+    pub fn is_syntax(&self) -> bool {
+        matches!(self, Self::Empty | Self::Unrecognized(_))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Unit {
+    Second,
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Unit {
+    fn seconds(self) -> f64 {
+        match self {
+            Self::Second => 1.0,
+            Self::Minute => 60.0,
+            Self::Hour => 3600.0,
+            Self::Day => 86400.0,
+        }
+    }
+
+    // 単位のない数字は直前の単位の次に小さい単位とみなす (1h30 = 1h30m)
+    fn smaller(self) -> Option<Self> {
+        match self {
+            Self::Second => None,
+            Self::Minute => Some(Self::Second),
+            Self::Hour => Some(Self::Minute),
+            Self::Day => Some(Self::Hour),
+        }
+    }
+}
+
+// 長いものから順に照合する
+const UNIT_WORDS: [(&str, Unit); 25] = [
+    ("seconds", Unit::Second), ("minutes", Unit::Minute), ("second", Unit::Second), ("minute", Unit::Minute),
+    ("hours", Unit::Hour), ("hour", Unit::Hour), ("secs", Unit::Second), ("mins", Unit::Minute),
+    ("days", Unit::Day), ("min", Unit::Minute), ("sec", Unit::Second), ("hrs", Unit::Hour), ("day", Unit::Day),
+    ("時間", Unit::Hour), ("分間", Unit::Minute), ("秒間", Unit::Second), ("日間", Unit::Day),
+    ("hr", Unit::Hour), ("h", Unit::Hour), ("m", Unit::Minute), ("s", Unit::Second), ("d", Unit::Day),
+    ("分", Unit::Minute), ("秒", Unit::Second), ("日", Unit::Day),
+];
+
+impl Scanner {
+    // 小数を含む数
+    fn decimal(&mut self) -> Option<f64> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if self.pos > start && self.peek() == Some('.')
+            && self.chars.get(self.pos + 1).is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.pos += 1;
+            }
+        }
+        if self.pos == start {
+            return None;
+        }
+        self.chars[start..self.pos].iter().collect::<String>().parse().ok()
+    }
+
+    fn unit(&mut self, units: &[Unit]) -> Option<Unit> {
+        UNIT_WORDS.iter()
+            .filter(|(_, unit)| units.contains(unit))
+            .find(|(word, _)| self.eat(word))
+            .map(|&(_, unit)| unit)
+    }
+
+    // 1:30, 1:30:15 (時:分[:秒])
+    fn clock_duration(&mut self, hours: f64) -> Result<f64, DurationError> {
+        let mut seconds = hours * Unit::Hour.seconds();
+        for unit in [Unit::Minute, Unit::Second] {
+            let Some(n) = self.number() else {
+                return Err(DurationError::Unrecognized(self.rest()));
+            };
+            if n > 59 {
+                return Err(DurationError::OutOfRange(n));
+            }
+            seconds += f64::from(n) * unit.seconds();
+            if !self.eat(":") {
+                break;
+            }
+        }
+        Ok(seconds)
+    }
+
+    // PT1H30M, P2D, P1DT12H
+    fn iso_duration(&mut self) -> Result<f64, DurationError> {
+        let mut seconds = 0.0;
+        let mut last = None;
+        let mut time_part = false;
+        while self.peek().is_some() {
+            if !time_part && self.eat("t") {
+                time_part = true;
+                continue;
+            }
+            let start = self.pos;
+            let value = self.decimal().ok_or_else(|| DurationError::Unrecognized(self.rest()))?;
+            let units: &[Unit] = if time_part { &[Unit::Hour, Unit::Minute, Unit::Second] } else { &[Unit::Day] };
+            let Some(unit) = self.unit(units) else {
+                return Err(DurationError::Unrecognized(self.rest_from(start)));
+            };
+            if last.is_some_and(|l| unit >= l) {
+                return Err(DurationError::UnitOrder(self.rest_from(start)));
+            }
+            seconds += value * unit.seconds();
+            last = Some(unit);
+        }
+        if last.is_none() {
+            return Err(DurationError::Unrecognized(self.rest_from(0)));
+        }
+        Ok(seconds)
+    }
+
+    // 1h30m15s, 90min, 1.5時間, 1時間半, 2d
+    fn compound_duration(&mut self) -> Result<f64, DurationError> {
+        let mut seconds = 0.0;
+        let mut last: Option<Unit> = None;
+        loop {
+            self.skip_spaces();
+            if self.peek().is_none() {
+                break;
+            }
+            let start = self.pos;
+            let value = self.decimal().ok_or_else(|| DurationError::Unrecognized(self.rest()))?;
+            if last.is_none() && self.eat(":") {
+                seconds += self.clock_duration(value)?;
+                last = Some(Unit::Second);
+                continue;
+            }
+            self.skip_spaces();
+
+            let unit = match self.unit(&[Unit::Day, Unit::Hour, Unit::Minute, Unit::Second]) {
+                Some(unit) => unit,
+                None => match last {
+                    None => Unit::Minute,
+                    Some(l) => l.smaller().ok_or_else(|| DurationError::Unrecognized(self.rest_from(start)))?
+                }
+            };
+            if last.is_some_and(|l| unit >= l) {
+                return Err(DurationError::UnitOrder(self.rest_from(start)));
+            }
+            seconds += value * unit.seconds();
+            last = Some(unit);
+
+            // 1時間半/1日半
+            if self.eat("半") {
+                seconds += unit.seconds() / 2.0;
+                last = unit.smaller();
+            }
+        }
+        Ok(seconds)
+    }
+}
+
+// 相対指定の時間（maxより長い時間は受け付けない）
+pub fn parse_duration(input: &str, max: Duration) -> Result<Duration, DurationError> {
+    let mut scanner = Scanner { chars: normalize(input).chars().collect(), pos: 0 };
+    scanner.skip_spaces();
+    if scanner.peek().is_none() {
+        return Err(DurationError::Empty);
+    }
+
+    let seconds = if scanner.eat("p") {
+        scanner.iso_duration()?
+    } else {
+        scanner.compound_duration()?
+    };
+
+    let seconds = seconds.round();
+    if seconds < 1.0 {
+        return Err(DurationError::Zero);
+    }
+    if seconds > max.num_seconds() as f64 {
+        return Err(DurationError::TooLong(max));
+    }
+    Ok(Duration::seconds(seconds as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn time_of_day(input: &str) -> Option<NaiveTime> {
+        TimeExpr::parse(input).ok()?.time_of_day()
+    }
+
+    fn hm(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn time_expr_clock() {
+        assert_eq!(time_of_day("23:30"), Some(hm(23, 30)));
+        assert_eq!(time_of_day("２３：３０"), Some(hm(23, 30)));
+        assert_eq!(time_of_day("23時半"), Some(hm(23, 30)));
+        assert_eq!(time_of_day("23時30分"), Some(hm(23, 30)));
+        assert_eq!(time_of_day("午後1時"), Some(hm(13, 0)));
+        assert_eq!(time_of_day("11:30pm"), Some(hm(23, 30)));
+        assert_eq!(time_of_day("12am"), Some(hm(0, 0)));
+        assert_eq!(time_of_day("正午"), Some(hm(12, 0)));
+        assert_eq!(time_of_day("明日 1時"), None);
+    }
+
+    #[test]
+    fn time_expr_rejects_duration_units() {
+        for input in ["1h", "1h30", "30m", "30min", "30分", "1時間"] {
+            assert_eq!(TimeExpr::parse(input), Err(TimeError::DurationLike(input.to_string())));
+        }
+        assert_eq!(TimeExpr::parse("明日 30m"), Err(TimeError::DurationLike("30m".to_string())));
+    }
+
+    #[test]
+    fn time_expr_errors() {
+        assert_eq!(TimeExpr::parse(""), Err(TimeError::Empty));
+        assert_eq!(TimeExpr::parse("1時30"), Err(TimeError::Unrecognized("1時30".to_string())));
+        assert_eq!(TimeExpr::parse("午後"), Err(TimeError::Unrecognized("午後".to_string())));
+        assert_eq!(TimeExpr::parse("明日 foo"), Err(TimeError::Unrecognized("foo".to_string())));
+        assert_eq!(TimeExpr::parse("午後13時"), Err(TimeError::MeridiemHour(13)));
+        assert_eq!(TimeExpr::parse("25:00"), Err(TimeError::HourOutOfRange(25)));
+        assert_eq!(TimeExpr::parse("1:60"), Err(TimeError::MinuteOutOfRange(60)));
+        assert_eq!(TimeExpr::parse("明日"), Err(TimeError::MissingHour));
+        assert_eq!(TimeExpr::parse("2/30 1:00"), Err(TimeError::InvalidDate { year: None, month: 2, day: 30 }));
+    }
+
+    #[test]
+    fn time_expr_resolve() {
+        let tz: Tz = "Asia/Tokyo".parse().unwrap();
+        // 2024-01-01 (月) 12:00 JST
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 3, 0, 0).unwrap();
+        let resolve = |input: &str| TimeExpr::parse(input).and_then(|t| t.resolve(tz, now));
+        let jst = |d: u32, h: u32, m: u32| tz.with_ymd_and_hms(2024, 1, d, h, m, 0).unwrap().naive_utc();
+
+        assert_eq!(resolve("13:00"), Ok(jst(1, 13, 0)));
+        assert_eq!(resolve("11:00"), Ok(jst(2, 11, 0)));
+        assert_eq!(resolve("明日 1時"), Ok(jst(2, 1, 0)));
+        assert_eq!(resolve("金曜 22時"), Ok(jst(5, 22, 0)));
+        assert_eq!(resolve("今日 11時"), Err(TimeError::Past));
+    }
+
+    #[test]
+    fn duration_forms() {
+        let max = Duration::days(30);
+        let ninety = Ok(Duration::minutes(90));
+        for input in ["90min", "90", "1.5h", "1h30m", "1h30", "1時間半", "1:30", "PT1H30M", "pt1.5h"] {
+            assert_eq!(parse_duration(input, max), ninety, "{}", input);
+        }
+        assert_eq!(parse_duration("45秒", max), Ok(Duration::seconds(45)));
+        assert_eq!(parse_duration("P1DT12H", max), Ok(Duration::hours(36)));
+    }
+
+    #[test]
+    fn duration_errors() {
+        let max = Duration::days(30);
+        assert_eq!(parse_duration(" ", max), Err(DurationError::Empty));
+        assert_eq!(parse_duration("30m1h", max), Err(DurationError::UnitOrder("1h".to_string())));
+        assert_eq!(parse_duration("1h1h", max), Err(DurationError::UnitOrder("1h".to_string())));
+        assert_eq!(parse_duration("PT30M1H", max), Err(DurationError::UnitOrder("1h".to_string())));
+        assert_eq!(parse_duration("1:75", max), Err(DurationError::OutOfRange(75)));
+        assert_eq!(parse_duration("0m", max), Err(DurationError::Zero));
+        assert_eq!(parse_duration("0.1s", max), Err(DurationError::Zero));
+        assert_eq!(parse_duration("31d", max), Err(DurationError::TooLong(max)));
+        assert_eq!(parse_duration("30d", max), Ok(max));
+        assert!(matches!(parse_duration("1x", max), Err(DurationError::Unrecognized(_))));
+    }
+}
@@ -0,0 +1,384 @@
+use serenity::{
+    model::{
+        voice::VoiceState,
+        guild::Guild,
+        id::{ChannelId, GuildId, UserId}
+    },
+    http::CacheHttp,
+    prelude::*
+};
+use std::{collections::HashSet, sync::Arc};
+use chrono::{Utc, Duration, NaiveDateTime};
+use chrono_tz::Tz;
+use crate::SqliteDatabase;
+use crate::job::{GuildSetting, Policy, ReservationOptions, Target, VoiceSession, VoiceBudget, MutedMember};
+use crate::schedule::{describe_duration, local_date, start_of_day};
+
+// 通話中の記録に確認時刻を残す間隔
+const SEEN_INTERVAL_SECS: u64 = 60;
+
+pub async fn voice_state_update(ctx: Context, old: Option<VoiceState>, new: VoiceState) {
+    let Some(guild_id) = new.guild_id else {
+        return;
+    };
+    let database = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<SqliteDatabase>().unwrap().clone()
+    };
+
+    // ミュート切り替えなどは無視し、通話への参加/移動/退出のみ扱う
+    let (joined, channel_id) = match (old.and_then(|o| o.channel_id), new.channel_id) {
+        (None, Some(new_channel)) => (true, new_channel),
+        (Some(old_channel), Some(new_channel)) if old_channel != new_channel => (false, new_channel),
+        (Some(_), None) => {
+            if let Err(why) = end_session(&database, new.user_id, guild_id, Utc::now().naive_utc()).await {
+                println!("end voice session: {}", why);
+            }
+            // 通話中でないとミュートを解除できないため、次の参加時に解除する
+            if let Err(why) = database.release_member_mute(new.user_id, guild_id).await {
+                println!("release mute: {}", why);
+            }
+            return;
+        },
+        _ => return
+    };
+
+    release_mutes(&ctx, &database, guild_id).await;
+
+    match enforce_lockout(&ctx, &database, new.user_id, guild_id, channel_id).await {
+        Ok(true) => return,
+        Ok(false) => {},
+        Err(why) => println!("enforce lockout: {}", why)
+    }
+    match enforce_quiet_hours(&ctx, &database, new.user_id, guild_id, channel_id).await {
+        Ok(true) => return,
+        Ok(false) => {},
+        Err(why) => println!("enforce quiet hours: {}", why)
+    }
+
+    if joined {
+        if let Err(why) = start_session(&database, new.user_id, guild_id).await {
+            println!("start voice session: {}", why);
+        }
+    }
+}
+
+// 段階的な切断が終わった/取り消されたミュートを、通話中のユーザーから解除する
+// （コマンドからはContext、JobRunnerからはCacheAndHttpで呼ばれる）
+pub async fn release_mutes(ctx: &impl CacheHttp, database: &SqliteDatabase, guild_id: GuildId) {
+    let in_voice: HashSet<UserId> = match ctx.cache().and_then(|cache| cache.guild(guild_id)) {
+        Some(guild) => guild.voice_states
+            .values()
+            .filter(|voice_state| voice_state.channel_id.is_some())
+            .map(|voice_state| voice_state.user_id)
+            .collect(),
+        None => return
+    };
+
+    let mutes = match database.get_released_mutes(guild_id).await {
+        Ok(mutes) => mutes,
+        Err(why) => {
+            println!("get released mutes: {}", why);
+            return;
+        }
+    };
+    for muted in mutes.iter().filter(|m| in_voice.contains(&m.userid())) {
+        if let Err(why) = unmute(ctx, database, muted, guild_id).await {
+            println!("unmute {}: {}", muted.user_id, why);
+        }
+    }
+}
+
+async fn unmute(ctx: &impl CacheHttp, database: &SqliteDatabase, muted: &MutedMember, guild_id: GuildId)
+                -> Result<(), String> {
+    guild_id.edit_member(ctx.http(), muted.userid(), |m| {
+        if muted.mute {
+            m.mute(false);
+        }
+        if muted.deaf {
+            m.deafen(false);
+        }
+        m
+    }).await.map_err(|why| why.to_string())?;
+
+    database.delete_muted_member(muted.userid(), guild_id).await.map_err(|why| why.to_string())?;
+    Ok(())
+}
+
+// 起動時/ギルド参加時に、保存された参加時刻を現在のボイス状態に合わせる
+pub async fn sync_sessions(ctx: &Context, guild: &Guild) {
+    let database = {
+        let data_read = ctx.data.read().await;
+        data_read.get::<SqliteDatabase>().unwrap().clone()
+    };
+
+    let in_voice: HashSet<UserId> = guild.voice_states
+        .values()
+        .filter(|voice_state| voice_state.channel_id.is_some())
+        .map(|voice_state| voice_state.user_id)
+        .collect();
+
+    let sessions = match database.get_guild_voice_sessions(guild.id).await {
+        Ok(sessions) => sessions,
+        Err(why) => {
+            println!("get voice sessions: {}", why);
+            return;
+        }
+    };
+    // 停止中に抜けたユーザーは、最後に通話中と確認した時刻までを1日の通話時間に加える
+    for session in sessions.iter().filter(|s| !in_voice.contains(&s.userid())) {
+        let ended_at = session.seen_at.unwrap_or(session.started_at);
+        if let Err(why) = end_session(&database, session.userid(), guild.id, ended_at).await {
+            println!("end voice session: {}", why);
+        }
+    }
+
+    // 停止中に過ぎた切断予約は削除されているため、通話中のユーザーは予約を作り直す
+    for &user_id in in_voice.iter() {
+        if let Err(why) = start_session(&database, user_id, guild.id).await {
+            println!("start voice session: {}", why);
+        }
+    }
+}
+
+// 停止中に抜けたユーザーの通話時間を見積もれるよう、通話中の記録に定期的に確認時刻を残す
+pub async fn record_seen(database: Arc<SqliteDatabase>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(SEEN_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        if let Err(why) = database.touch_voice_sessions(Utc::now().naive_utc()).await {
+            println!("touch voice sessions: {}", why);
+        }
+    }
+}
+
+// 設定変更時に、通話中のユーザーの上限の切断予約を作り直す
+pub async fn reschedule_sessions(database: &SqliteDatabase, guild_id: GuildId) -> Result<(), sqlx::Error> {
+    let setting = database.get_guild_setting(guild_id).await?;
+    for session in database.get_guild_voice_sessions(guild_id).await? {
+        schedule_session_limit(database, &session, &setting, guild_id).await?;
+    }
+    Ok(())
+}
+
+async fn start_session(database: &SqliteDatabase, user_id: UserId, guild_id: GuildId)
+                       -> Result<(), sqlx::Error> {
+    let session = database.start_voice_session(user_id, guild_id, Utc::now().naive_utc()).await?;
+    let setting = database.get_guild_setting(guild_id).await?;
+    schedule_session_limit(database, &session, &setting, guild_id).await?;
+    schedule_budget(database, &session, guild_id).await
+}
+
+// 通話を抜けたら今日の使用時間に加算する
+async fn end_session(database: &SqliteDatabase, user_id: UserId, guild_id: GuildId, ended_at: NaiveDateTime)
+                     -> Result<(), sqlx::Error> {
+    let Some(session) = database.end_voice_session(user_id, guild_id).await? else {
+        return Ok(());
+    };
+    let Some(budget) = database.get_voice_budget(user_id, guild_id).await? else {
+        return Ok(());
+    };
+
+    let tz = database.get_timezone(user_id, guild_id).await?;
+    let used = budget_used(&budget, Some(&session), tz, ended_at);
+    database.update_voice_budget_usage(user_id, guild_id, &local_date(tz, ended_at).to_string(),
+                                       used.num_seconds()).await?;
+
+    if let Some(reservation_id) = budget.reservation_id {
+        database.delete_reservation(reservation_id, user_id, guild_id).await?;
+    }
+    Ok(())
+}
+
+// 今日の使用時間（通話中なら今日の0時以降の分を含む）
+pub fn budget_used(budget: &VoiceBudget, session: Option<&VoiceSession>, tz: Tz, now: NaiveDateTime) -> Duration {
+    let today = local_date(tz, now);
+    let current = session.map_or(Duration::zero(), |session| {
+        (now - session.started_at.max(start_of_day(tz, today))).max(Duration::zero())
+    });
+    budget.used_on(today) + current
+}
+
+// 1日の通話時間を使い切る時刻に切断する予約（切断前通知を含む）
+pub async fn schedule_budget(database: &SqliteDatabase, session: &VoiceSession, guild_id: GuildId)
+                             -> Result<(), sqlx::Error> {
+    let user_id = session.userid();
+    let Some(budget) = database.get_voice_budget(user_id, guild_id).await? else {
+        return Ok(());
+    };
+    if let Some(reservation_id) = budget.reservation_id {
+        database.delete_reservation(reservation_id, user_id, guild_id).await?;
+    }
+
+    let tz = database.get_timezone(user_id, guild_id).await?;
+    let now = Utc::now().naive_utc();
+    let remaining = budget.daily() - budget_used(&budget, Some(session), tz, now);
+    let mut disconnect_at = now + remaining.max(Duration::zero());
+
+    // 使い切る前に日付が変われば、翌日の上限まで通話できる
+    let tomorrow = start_of_day(tz, local_date(tz, now) + Duration::days(1));
+    if disconnect_at >= tomorrow {
+        disconnect_at = tomorrow + budget.daily();
+    }
+
+    let options = ReservationOptions { policy: Some(Policy::Budget), ..Default::default() };
+    let reservation_id = database.insert_reservation(user_id, guild_id, &Target::User(user_id), &options).await?;
+    database.insert_disconnect_jobs(reservation_id, disconnect_at, user_id, guild_id).await?;
+    database.update_voice_budget_reservation(user_id, guild_id, Some(reservation_id)).await?;
+    Ok(())
+}
+
+// 参加時刻から連続通話の上限で切断する予約（切断前通知を含む）
+async fn schedule_session_limit(database: &SqliteDatabase, session: &VoiceSession,
+                                setting: &GuildSetting, guild_id: GuildId) -> Result<(), sqlx::Error> {
+    let user_id = session.userid();
+    if let Some(reservation_id) = session.reservation_id {
+        database.delete_reservation(reservation_id, user_id, guild_id).await?;
+    }
+
+    let reservation_id = match setting.max_session() {
+        Some(max_session) => {
+            let disconnect_at = (session.started_at + max_session).max(Utc::now().naive_utc());
+            let options = ReservationOptions { policy: Some(Policy::Session), ..Default::default() };
+            let reservation_id = database.insert_reservation(user_id, guild_id, &Target::User(user_id), &options).await?;
+            database.insert_disconnect_jobs(reservation_id, disconnect_at, user_id, guild_id).await?;
+            Some(reservation_id)
+        },
+        None => None
+    };
+
+    database.update_voice_session_reservation(user_id, guild_id, reservation_id).await?;
+    Ok(())
+}
+
+async fn direct_message(ctx: &Context, user_id: UserId, content: String) -> Result<(), String> {
+    user_id.create_dm_channel(&ctx.http).await
+        .map_err(|why| why.to_string())?
+        .send_message(&ctx.http, |m| m.content(content))
+        .await
+        .map_err(|why| why.to_string())?;
+    Ok(())
+}
+
+// ロックアウト中の再参加を切断し、試行回数を数える（切断したらtrue）
+async fn enforce_lockout(ctx: &Context, database: &SqliteDatabase, user_id: UserId, guild_id: GuildId,
+                         channel_id: ChannelId) -> Result<bool, String> {
+    let lockout = match database.get_active_lockout(user_id, guild_id).await {
+        Ok(Some(lockout)) => lockout,
+        Ok(None) => return Ok(false),
+        Err(why) => return Err(why.to_string())
+    };
+    if lockout.allows(channel_id) {
+        return Ok(false);
+    }
+
+    guild_id.disconnect_member(&ctx.http, user_id).await.map_err(|why| why.to_string())?;
+    let attempts = database.count_lockout_attempt(lockout.id).await.map_err(|why| why.to_string())?;
+
+    let remaining = describe_duration(lockout.until - Utc::now().naive_utc());
+    direct_message(ctx, user_id,
+                   format!("ロックアウト中のため通話を切断しました（{0}回目）\n残り{1} (<t:{2}:T>まで)",
+                           attempts, remaining, lockout.timestamp())).await?;
+
+    Ok(true)
+}
+
+// ギルドの静粛時間中の参加を切断する（切断したらtrue）
+async fn enforce_quiet_hours(ctx: &Context, database: &SqliteDatabase, user_id: UserId, guild_id: GuildId,
+                             channel_id: ChannelId) -> Result<bool, String> {
+    let setting = database.get_guild_setting(guild_id).await.map_err(|why| why.to_string())?;
+    let Some(quiet_hours) = setting.quiet_hours() else {
+        return Ok(false);
+    };
+    // 開始時の移動先のチャンネルには参加できる
+    let destination = ctx.cache.guild(guild_id).and_then(|guild| setting.action().destination(&guild));
+    if destination == Some(channel_id) {
+        return Ok(false);
+    }
+
+    let now = Utc::now().naive_utc();
+    let tz = setting.tz();
+    if !quiet_hours.contains(now.and_utc().with_timezone(&tz).time()) {
+        return Ok(false);
+    }
+    if let Some(role_id) = setting.quiet_role() {
+        let member = guild_id.member(ctx, user_id).await.map_err(|why| why.to_string())?;
+        if !member.roles.contains(&role_id) {
+            return Ok(false);
+        }
+    }
+
+    guild_id.disconnect_member(&ctx.http, user_id).await.map_err(|why| why.to_string())?;
+
+    let end = quiet_hours.end_after(tz, now);
+    direct_message(ctx, user_id,
+                   format!("静粛時間 ({0}) のため通話を切断しました\n残り{1} (<t:{2}:T>まで)",
+                           quiet_hours.describe(), describe_duration(end - now), end.and_utc().timestamp())).await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::disconnect::Disconnect;
+    use crate::database::tests::{test_database, GUILD, USER};
+
+    #[test]
+    fn budget_used_across_local_midnight() {
+        let tokyo = chrono_tz::Asia::Tokyo;
+        // 東京の時刻をUTCで表す
+        let at = |d, h, m| chrono::NaiveDate::from_ymd_opt(2024, 1, d).unwrap().and_hms_opt(h, m, 0).unwrap()
+            - Duration::hours(9);
+        let budget = VoiceBudget { daily_minutes: 120, used_seconds: 3600, day: "2024-01-01".to_string(), reservation_id: None };
+        let session = |started_at| VoiceSession { user_id: 2, started_at, reservation_id: None, seen_at: None };
+
+        // 同じ日なら記録済みの分に通話中の分を足す
+        assert_eq!(budget_used(&budget, None, tokyo, at(1, 23, 30)), Duration::minutes(60));
+        assert_eq!(budget_used(&budget, Some(&session(at(1, 23, 0))), tokyo, at(1, 23, 30)), Duration::minutes(90));
+
+        // 日付が変われば前日の記録は数えず、通話中の分も0時から数える
+        assert_eq!(budget_used(&budget, Some(&session(at(1, 23, 0))), tokyo, at(2, 0, 30)), Duration::minutes(30));
+        assert_eq!(budget_used(&budget, Some(&session(at(2, 0, 10))), tokyo, at(2, 0, 30)), Duration::minutes(20));
+        assert_eq!(budget_used(&budget, None, tokyo, at(2, 0, 30)), Duration::zero());
+
+        // 同じ時刻でもUTCのユーザーにとってはまだ前日
+        assert_eq!(budget_used(&budget, None, Tz::UTC, at(2, 0, 30)), Duration::minutes(60));
+    }
+
+    #[tokio::test]
+    async fn session_limit_follows_the_guild_setting() {
+        let database = test_database("session_limit").await;
+        let started_at = Utc::now().naive_utc() - Duration::minutes(10);
+        database.start_voice_session(USER, GUILD, started_at).await.unwrap();
+
+        // 上限がなければ予約しない
+        reschedule_sessions(&database, GUILD).await.unwrap();
+        let session = database.get_voice_session(USER, GUILD).await.unwrap().unwrap();
+        assert_eq!(session.reservation_id, None);
+
+        // 参加時刻から上限の時間で切断する
+        database.update_guild_max_session(GUILD, Some(60)).await.unwrap();
+        reschedule_sessions(&database, GUILD).await.unwrap();
+        let session = database.get_voice_session(USER, GUILD).await.unwrap().unwrap();
+        let first = session.reservation_id.unwrap();
+        let disconnect_at = database.get_job_time(first, Disconnect::NAME).await.unwrap();
+        assert_eq!(disconnect_at, Some(started_at + Duration::minutes(60)));
+        // 設定による予約は/showや/cancelの一覧には出さない
+        assert!(database.get_user_reservations(USER, GUILD).await.unwrap().is_empty());
+
+        // 既に上限を過ぎていれば今すぐ切断し、前の予約は作り直す
+        database.update_guild_max_session(GUILD, Some(5)).await.unwrap();
+        reschedule_sessions(&database, GUILD).await.unwrap();
+        let session = database.get_voice_session(USER, GUILD).await.unwrap().unwrap();
+        let second = session.reservation_id.unwrap();
+        assert!(database.get_reservation(first).await.unwrap().is_none());
+        let disconnect_at = database.get_job_time(second, Disconnect::NAME).await.unwrap().unwrap();
+        assert!(disconnect_at > started_at + Duration::minutes(5));
+        assert!(disconnect_at <= Utc::now().naive_utc());
+
+        database.update_guild_max_session(GUILD, None).await.unwrap();
+        reschedule_sessions(&database, GUILD).await.unwrap();
+        assert!(database.get_reservation(second).await.unwrap().is_none());
+    }
+}